@@ -1,73 +1,604 @@
-// This shader computes the chromatic aberration effect
-
-#import bevy_pbr::utils
-
-// Since post processing is a fullscreen effect, we use the fullscreen vertex shader provided by bevy.
-// This will import a vertex shader that renders a single fullscreen triangle.
-//
-// A fullscreen triangle is a single triangle that covers the entire screen.
-// The box in the top left in that diagram is the screen. The 4 x are the corner of the screen
-//
-// Y axis
-//  1 |  x-----x......
-//  0 |  |  s  |  . ´
-// -1 |  x_____x´
-// -2 |  :  .´
-// -3 |  :´
-//    +---------------  X axis
-//      -1  0  1  2  3
-//
-// As you can see, the triangle ends up bigger than the screen.
-//
-// You don't need to worry about this too much since bevy will compute the correct UVs for you.
+#import bevy_render::view
 #import bevy_core_pipeline::fullscreen_vertex_shader
 
+// Lights everything the camera sees, see LightingNode in lighting_plugin.rs. `accumulate` renders the light into
+// the light texture, then `fragment` multiplies it over what the main pass rendered.
+
+// What the main pass rendered, only read by `fragment`
 @group(0) @binding(0)
 var screen_texture: texture_2d<f32>;
 @group(0) @binding(1)
-var texture_sampler: sampler;
-
-const MAX_LIGHTS: u32 = 32u;
+var screen_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> view: View;
+// Written by `accumulate`, only read by `fragment`
+@group(0) @binding(3)
+var light_texture: texture_2d<f32>;
+@group(0) @binding(4)
+var light_sampler: sampler;
 
-struct LightSource {
-    position: vec2<f32>,
+struct GpuLightSource {
     color: vec4<f32>,
+    position: vec2<f32>,
     intensity: f32,
     radius: f32,
+    is_active: u32,
+    source_radius: f32,
+    // 0 inverse square, 1 linear, 2 smoothstep, 3 curve texture
+    falloff: u32,
+    curve_index: u32,
+    // Spotlights only, point lights have a cos_outer below -1
+    direction: vec2<f32>,
+    cos_inner: f32,
+    cos_outer: f32,
+    // Half axes of line and area lights, zero for point lights
+    extent_a: vec2<f32>,
+    extent_b: vec2<f32>,
+    // Above the map, for normal mapping and to shine over low occluders
+    height: f32,
+    // Bitmask, only occluders sharing a layer block the light
+    layers: u32,
 };
 
-struct LightSourceArray {
-    values: array<LightSource, MAX_LIGHTS>
+// One world space edge of an occluder
+struct GpuSegment {
+    a: vec2<f32>,
+    b: vec2<f32>,
+    layers: u32,
+    // How far the occluder reaches above the map, f32 max for occluders that block every light
+    height: f32,
+    // What the occluder lets through, packed like pack4x8unorm. Rays only get tinted where they first cross it.
+    transmittance: u32,
+    // Index of the first segment of the same occluder, the segments of an occluder come in a row. Keeps the struct at
+    // the 32 bytes the stride of uniform arrays needs.
+    first: u32,
 };
 
-@group(0) @binding(2)
-var<uniform> light_sources: LightSourceArray;
+struct LightingGlobals {
+    light_count: u32,
+    segment_count: u32,
+    ambient: vec3<f32>,
+    darkness: f32,
+    // How far emissive pixels light up their surroundings in world units, 0 turns it off
+    emissive_spread: f32,
+    emissive_light: f32,
+    // Uniform grid over the segments, see OccluderGrid in occluder_grid.rs. A size of zero means no segments.
+    grid_origin: vec2<f32>,
+    grid_cell_size: f32,
+    grid_size: vec2<u32>,
+    // Corner of texel (0, 0) of the distance field and its size in world units
+    field_origin: vec2<f32>,
+    field_texel_size: f32,
+    // The normal, emissive and baked maps cover the map, which is centered on the world origin with one pixel per
+    // world unit
+    map_size: vec2<f32>,
+};
+
+// The segments of a grid cell are grid_indices[start..start + count]
+struct GpuGridCell {
+    start: u32,
+    count: u32,
+};
 
+#ifdef NO_STORAGE_BUFFERS
+// WebGL2 fallback, keep in sync with MAX_PACKED_LIGHTS / MAX_PACKED_SEGMENTS in lighting_material_plugin.rs
+const MAX_PACKED_LIGHTS = 128u;
+const MAX_PACKED_SEGMENTS = 512u;
 
+struct PackedLights {
+    values: array<GpuLightSource, MAX_PACKED_LIGHTS>,
+};
 
-@fragment
-fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
-    var color: vec4<f32> = textureSample(screen_texture, texture_sampler, in.uv);
+struct PackedSegments {
+    values: array<GpuSegment, MAX_PACKED_SEGMENTS>,
+};
+
+@group(1) @binding(2)
+var<uniform> lights: PackedLights;
 
+@group(1) @binding(3)
+var<uniform> segments: PackedSegments;
 
+fn get_light(i: u32) -> GpuLightSource {
+    return lights.values[i];
+}
 
-    // Calculate the screen position of the fragment
-    var screen_position: vec2<f32> = in.uv * vec2<f32>(textureDimensions(screen_texture));
+fn get_segment(i: u32) -> GpuSegment {
+    return segments.values[i];
+}
+#else
+@group(1) @binding(2)
+var<storage, read> lights: array<GpuLightSource>;
 
-    var light_intensity: f32 = 0.0;
-    for (var i: u32 = 0u; i < MAX_LIGHTS; i = i + 1u) {
-        let light: LightSource = light_sources.values[i];
+@group(1) @binding(3)
+var<storage, read> segments: array<GpuSegment>;
 
-        // Calculate the distance from the light source to the fragment
-        let distance: f32 = length(position.xy - light.position);
+fn get_light(i: u32) -> GpuLightSource {
+    return lights[i];
+}
+
+fn get_segment(i: u32) -> GpuSegment {
+    return segments[i];
+}
+
+@group(1) @binding(12)
+var<storage, read> grid_cells: array<GpuGridCell>;
+
+@group(1) @binding(13)
+var<storage, read> grid_indices: array<u32>;
+#endif
+
+@group(1) @binding(4)
+var<uniform> lighting_globals: LightingGlobals;
+
+// One row per custom falloff curve
+@group(1) @binding(5)
+var falloff_curves: texture_2d<f32>;
+
+// Only sampled with NORMAL_MAP, otherwise this and the other optional textures are fallback textures
+@group(1) @binding(6)
+var normal_map: texture_2d<f32>;
+@group(1) @binding(7)
+var normal_sampler: sampler;
+
+// Only sampled with EMISSIVE_MAP
+@group(1) @binding(8)
+var emissive_map: texture_2d<f32>;
+@group(1) @binding(9)
+var emissive_sampler: sampler;
+
+// Only sampled with BAKED_LIGHTMAP, holds the light of the static lights divided by BAKED_LIGHT_RANGE
+@group(1) @binding(10)
+var baked_lightmap: texture_2d<f32>;
+@group(1) @binding(11)
+var baked_sampler: sampler;
+
+// Only loaded with DISTANCE_FIELD, signed distance to the nearest occluder in world units
+@group(1) @binding(14)
+var distance_field: texture_2d<f32>;
+
+// Keep in sync with BAKED_LIGHT_RANGE in bake.rs
+const BAKED_LIGHT_RANGE = 4.0;
+
+// Where the line A-B crosses the line C-D, from 0 at A to 1 at B, or -1 if they don't cross
+fn crossing(A: vec2<f32>, B: vec2<f32>, C: vec2<f32>, D: vec2<f32>) -> f32 {
+// calculate the direction of the lines
+  var uA = ((D.x-C.x)*(A.y-C.y) - (D.y-C.y)*(A.x-C.x)) / ((D.y-C.y)*(B.x-A.x) - (D.x-C.x)*(B.y-A.y));
+  var uB = ((B.x-A.x)*(A.y-C.y) - (B.y-A.y)*(A.x-C.x)) / ((D.y-C.y)*(B.x-A.x) - (D.x-C.x)*(B.y-A.y));
+
+  // if uA and uB are between 0-1, lines are colliding
+  if (uA >= 0.0 && uA <= 1.0 && uB >= 0.0 && uB <= 1.0) {
+    return uA;
+  }
+  return -1.0;
+}
+
+// Where the segment blocks the ray from `start` on the map to `end` on a light `height` above the map, or -1 if it
+// doesn't. It only does if it shares a layer with the light and reaches above the ray where the ray crosses it.
+// Keep in sync with `blocking_crossing` in cpu_lighting.rs
+fn blocking_crossing(segment: GpuSegment, start: vec2<f32>, end: vec2<f32>, layers: u32, height: f32) -> f32 {
+    if((segment.layers & layers) == 0u) {
+        return -1.0;
+    }
+    let t = crossing(start, end, segment.a, segment.b);
+    return select(-1.0, t, t >= 0.0 && segment.height > height * t);
+}
+
+fn segment_transmittance(segment: GpuSegment) -> vec3<f32> {
+    return unpack4x8unorm(segment.transmittance).rgb;
+}
+
+// Whether the ray crosses no other edge of the occluder of segment `index` before it crosses this one at `t`. Every
+// occluder tints the light once, no matter how many of its edges the ray crosses.
+// Keep in sync with `is_first_crossing` in cpu_lighting.rs
+fn is_first_crossing(index: u32, t: f32, start: vec2<f32>, end: vec2<f32>, layers: u32, height: f32) -> bool {
+    let first = get_segment(index).first;
+    for(var j = first; j < lighting_globals.segment_count; j = j + 1u) {
+        let other = get_segment(j);
+        if(other.first != first) {
+            break;
+        }
+        if(j == index) {
+            continue;
+        }
+        let other_t = blocking_crossing(other, start, end, layers, height);
+        if(other_t >= 0.0 && (other_t < t || (other_t == t && j < index))) {
+            return false;
+        }
+    }
+    return true;
+}
+
+// PCG hash, keep in sync with `hash` in cpu_lighting.rs
+fn hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Random value in 0..1 that stays the same for every point inside the same world unit
+fn pixel_noise(p: vec2<f32>) -> f32 {
+    let cell = vec2<i32>(floor(p));
+    return f32(hash(bitcast<u32>(cell.x) ^ hash(bitcast<u32>(cell.y)))) / 4294967295.0;
+}
+
+#ifdef NO_STORAGE_BUFFERS
+// There is no grid on WebGL2, every segment gets tested
+fn segments_transmittance(start: vec2<f32>, end: vec2<f32>, layers: u32, height: f32) -> vec3<f32> {
+    var transmitted = vec3<f32>(1.0);
+    for(var j = 0u; j < lighting_globals.segment_count; j = j + 1u) {
+        let segment = get_segment(j);
+        let t = blocking_crossing(segment, start, end, layers, height);
+        if(t >= 0.0 && is_first_crossing(j, t, start, end, layers, height)) {
+            transmitted = transmitted * segment_transmittance(segment);
+            if(all(transmitted <= vec3<f32>(0.0))) {
+                break;
+            }
+        }
+    }
+    return transmitted;
+}
+#else
+// A segment can be in several cells, it only counts in the one the ray crosses it in, which covers the ray from
+// `t_enter` to `t_exit`
+fn cell_transmittance(
+    cell: vec2<i32>,
+    t_enter: f32,
+    t_exit: f32,
+    start: vec2<f32>,
+    end: vec2<f32>,
+    layers: u32,
+    height: f32,
+) -> vec3<f32> {
+    let grid_cell = grid_cells[u32(cell.y) * lighting_globals.grid_size.x + u32(cell.x)];
+    var transmitted = vec3<f32>(1.0);
+    for(var j = 0u; j < grid_cell.count; j = j + 1u) {
+        let index = grid_indices[grid_cell.start + j];
+        let segment = get_segment(index);
+        let t = blocking_crossing(segment, start, end, layers, height);
+        if(t >= 0.0 && t >= t_enter && t < t_exit && is_first_crossing(index, t, start, end, layers, height)) {
+            transmitted = transmitted * segment_transmittance(segment);
+        }
+    }
+    return transmitted;
+}
+
+// How much of the light at `end` reaches `start` through the segments, every translucent occluder in the way tints
+// it. Only tests the segments in the grid cells along the ray, keep in sync with `OccluderGrid::visit_cells`.
+// `layers` and `height` belong to the light at `end`, see `blocking_crossing`.
+fn segments_transmittance(start: vec2<f32>, end: vec2<f32>, layers: u32, height: f32) -> vec3<f32> {
+    let grid_size = lighting_globals.grid_size;
+    if(grid_size.x == 0u) {
+        return vec3<f32>(1.0);
+    }
+
+    // Clip the ray to the grid, there are no segments outside of it
+    let cell_size = lighting_globals.grid_cell_size;
+    let grid_min = lighting_globals.grid_origin;
+    let grid_max = grid_min + vec2<f32>(grid_size) * cell_size;
+    let delta = end - start;
+    let flat = abs(delta) < vec2<f32>(1e-6);
+    if(any(flat & ((start < grid_min) | (start > grid_max)))) {
+        return vec3<f32>(1.0);
+    }
+    let safe_delta = select(delta, vec2<f32>(1.0), flat);
+    let t0 = (grid_min - start) / safe_delta;
+    let t1 = (grid_max - start) / safe_delta;
+    let t_near = select(min(t0, t1), vec2<f32>(0.0), flat);
+    let t_far = select(max(t0, t1), vec2<f32>(1.0), flat);
+    let t_min = max(max(t_near.x, t_near.y), 0.0);
+    let t_max = min(min(t_far.x, t_far.y), 1.0);
+    if(t_min > t_max) {
+        return vec3<f32>(1.0);
+    }
+
+    // Amanatides & Woo, step into whichever neighbor cell the ray reaches first
+    let last = vec2<i32>(grid_size) - 1;
+    var cell = clamp(vec2<i32>(floor((start + delta * t_min - grid_min) / cell_size)), vec2<i32>(0), last);
+    let step = select(vec2<i32>(sign(delta)), vec2<i32>(0), flat);
+    let t_delta = select(cell_size / abs(safe_delta), vec2<f32>(1e30), flat);
+    let boundary = grid_min + (vec2<f32>(cell) + select(vec2<f32>(0.0), vec2<f32>(1.0), delta > vec2<f32>(0.0))) * cell_size;
+    var t_next = select((boundary - start) / safe_delta, vec2<f32>(1e30), flat);
+
+    var transmitted = vec3<f32>(1.0);
+    var t_enter = -1e30;
+    for(var i = 0u; i < grid_size.x + grid_size.y; i = i + 1u) {
+        let t_exit = min(t_next.x, t_next.y);
+        let is_last = t_exit > t_max;
+        transmitted = transmitted * cell_transmittance(cell, t_enter, select(t_exit, 1e30, is_last), start, end, layers, height);
+        if(is_last || all(transmitted <= vec3<f32>(0.0))) {
+            break;
+        }
+        t_enter = t_exit;
+        if(t_next.x < t_next.y) {
+            cell.x = cell.x + step.x;
+            t_next.x = t_next.x + t_delta.x;
+        } else {
+            cell.y = cell.y + step.y;
+            t_next.y = t_next.y + t_delta.y;
+        }
+        if(any(cell < vec2<i32>(0)) || any(cell > last)) {
+            break;
+        }
+    }
+    return transmitted;
+}
+#endif
+
+#ifdef DISTANCE_FIELD
+// Keep in sync with MAX_MARCH_STEPS in distance_field.rs
+const MAX_MARCH_STEPS = 128u;
+
+// Outside of the field this is the distance to its border, which is never more than the distance to an occluder.
+// Keep in sync with `DistanceField::distance`.
+fn field_distance(p: vec2<f32>) -> f32 {
+    let size = textureDimensions(distance_field);
+    let texel = (p - lighting_globals.field_origin) / lighting_globals.field_texel_size;
+    let outside = max(max(-texel, texel - vec2<f32>(size)), vec2<f32>(0.0));
+    if(any(outside > vec2<f32>(0.0))) {
+        return length(outside) * lighting_globals.field_texel_size;
+    }
+    let coord = min(vec2<i32>(texel), vec2<i32>(size) - 1);
+    return textureLoad(distance_field, coord, 0).r;
+}
 
-        // Calculate the light influence based on the distance and light radius
-        let influence: f32 = max(0.0, 1.0 - distance / light.radius);
+// Sphere tracing through the distance field, keep in sync with `DistanceField::march`. Returns how far the ray got,
+// from 0 at `start` to 1 at `end`, or -1 if it hit an occluder.
+fn march(start: vec2<f32>, end: vec2<f32>) -> f32 {
+    let to_end = end - start;
+    let ray_length = length(to_end);
+    let direction = to_end / max(ray_length, 0.0001);
+    // The nearest texel can be off by half a texel diagonal
+    let slack = 0.7072 * lighting_globals.field_texel_size;
 
-        // Apply the light color, intensity, and influence to the fragment
-        light_intensity = light.intensity * influence;
+    var t = 0.0;
+    for(var i = 0u; i < MAX_MARCH_STEPS; i = i + 1u) {
+        let distance = field_distance(start + direction * t);
+        if(distance <= 0.0) {
+            return -1.0;
+        }
+        t = t + max(distance - slack, 0.5 * lighting_globals.field_texel_size);
+        if(t >= ray_length) {
+            return 1.0;
+        }
     }
+    return t / ray_length;
+}
+
+// The field holds every occluder as if it was opaque, so light layers, occluder heights and translucency are ignored.
+// Rays crawling along an occluder run out of steps, the segments tell whether the light gets past it.
+fn transmittance(start: vec2<f32>, end: vec2<f32>, layers: u32, height: f32) -> vec3<f32> {
+    let marched = march(start, end);
+    if(marched < 0.0) {
+        return vec3<f32>(0.0);
+    }
+    if(marched >= 1.0) {
+        return vec3<f32>(1.0);
+    }
+    return segments_transmittance(mix(start, end, marched), end, layers, height);
+}
+#else
+fn transmittance(start: vec2<f32>, end: vec2<f32>, layers: u32, height: f32) -> vec3<f32> {
+    return segments_transmittance(start, end, layers, height);
+}
+#endif
+
+const SHADOW_SAMPLES = 8u;
+// Point lights spread over line and area lights
+const AREA_SAMPLES = 8u;
+
+// How much of the light reaches `position`, from 0 to 1 for every color channel. A light with a source radius is
+// treated as a line across the direction to the pixel, which gets sampled with stratified jittered shadow rays.
+fn light_visibility(position: vec2<f32>, light: GpuLightSource) -> vec3<f32> {
+    if(light.source_radius <= 0.0) {
+        return transmittance(position, light.position, light.layers, light.height);
+    }
+
+    let to_light = light.position - position;
+    let across = vec2<f32>(-to_light.y, to_light.x) / max(length(to_light), 0.0001);
+    let jitter = pixel_noise(position);
+
+    var visible = vec3<f32>(0.0);
+    for(var i = 0u; i < SHADOW_SAMPLES; i = i + 1u) {
+        let t = (f32(i) + jitter) / f32(SHADOW_SAMPLES) * 2.0 - 1.0;
+        let end = light.position + across * light.source_radius * t;
+        visible = visible + transmittance(position, end, light.layers, light.height);
+    }
+    return visible / f32(SHADOW_SAMPLES);
+}
+
+// Linear interpolation between the texels of a row of `falloff_curves`, keep in sync with `sample_falloff_curve`
+// in cpu_lighting.rs
+fn sample_falloff_curve(row: u32, t: f32) -> f32 {
+    let size = textureDimensions(falloff_curves);
+    let last = u32(size.x) - 1u;
+    let y = i32(min(row, u32(size.y) - 1u));
+    let x = clamp(t, 0.0, 1.0) * f32(last);
+    let x0 = u32(floor(x));
+    let x1 = min(x0 + 1u, last);
+    let a = textureLoad(falloff_curves, vec2<i32>(i32(x0), y), 0).r;
+    let b = textureLoad(falloff_curves, vec2<i32>(i32(x1), y), 0).r;
+    return mix(a, b, x - floor(x));
+}
+
+// `t` is the distance to the light divided by its radius
+fn falloff(light: GpuLightSource, t: f32) -> f32 {
+    switch light.falloff {
+        case 1u: {
+            return 1.0 - t;
+        }
+        case 2u: {
+            return 1.0 - smoothstep(0.0, 1.0, t);
+        }
+        case 3u: {
+            return sample_falloff_curve(light.curve_index, t);
+        }
+        default: {
+            // Inverse square, windowed so it reaches zero at the radius
+            let window = clamp(1.0 - t * t * t * t, 0.0, 1.0);
+            return window * window / (1.0 + 25.0 * t * t);
+        }
+    }
+}
+
+// Angular falloff of spotlights, 1 inside the inner cone and 0 outside the outer cone
+fn spot_factor(light: GpuLightSource, origin: vec2<f32>, position: vec2<f32>) -> f32 {
+    if(light.cos_outer < -1.0) {
+        return 1.0;
+    }
+    let to_pixel = position - origin;
+    let cos_angle = dot(light.direction, to_pixel / max(length(to_pixel), 0.0001));
+    return smoothstep(light.cos_outer, light.cos_inner, cos_angle);
+}
+
+// Falloff and spot factor of light emitted at `origin`, without any shadows
+fn attenuation(light: GpuLightSource, origin: vec2<f32>, position: vec2<f32>) -> f32 {
+    let light_distance = length(origin - position);
+    if(light_distance >= light.radius) {
+        return 0.0;
+    }
+    return falloff(light, light_distance / light.radius) * spot_factor(light, origin, position);
+}
+
+fn is_area_light(light: GpuLightSource) -> bool {
+    return any(light.extent_a != vec2<f32>(0.0)) || any(light.extent_b != vec2<f32>(0.0));
+}
+
+// How much of the light reaches `position`, with shadows. Line and area lights are approximated by point lights
+// spread over the emitter with stratified jittered positions, every one with its own shadow ray. Translucent
+// occluders in the way tint the light.
+fn light_contribution(light: GpuLightSource, position: vec2<f32>) -> vec3<f32> {
+    if(!is_area_light(light)) {
+        let point_attenuation = attenuation(light, light.position, position);
+        if(point_attenuation <= 0.0) {
+            return vec3<f32>(0.0);
+        }
+        // Partially occluded pixels are in the penumbra
+        return point_attenuation * light_visibility(position, light);
+    }
+
+    let jitter = pixel_noise(position);
+    var sum = vec3<f32>(0.0);
+    for(var i = 0u; i < AREA_SAMPLES; i = i + 1u) {
+        let u = (f32(i) + jitter) / f32(AREA_SAMPLES) * 2.0 - 1.0;
+        let v = fract(jitter + f32(i) * 0.618034) * 2.0 - 1.0;
+        let origin = light.position + light.extent_a * u + light.extent_b * v;
+        let sample_attenuation = attenuation(light, origin, position);
+        if(sample_attenuation > 0.0) {
+            sum = sum + sample_attenuation * transmittance(position, origin, light.layers, light.height);
+        }
+    }
+    return sum / f32(AREA_SAMPLES);
+}
+
+// Lambert term of a normal mapped pixel, the light sits `height` above the map
+fn normal_shading(light: GpuLightSource, position: vec2<f32>, normal: vec3<f32>) -> f32 {
+    let to_light = vec3<f32>(light.position - position, light.height);
+    return max(dot(normal, normalize(to_light)), 0.0);
+}
+
+const EMISSIVE_SPREAD_SAMPLES = 12u;
+
+// Light spilled onto `uv` of the map by the emissive pixels around it, sampled on two jittered rings
+fn emissive_spill(uv: vec2<f32>, position: vec2<f32>) -> vec3<f32> {
+    if(lighting_globals.emissive_spread <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+
+    let map_size = lighting_globals.map_size;
+    let jitter = pixel_noise(position) * 6.2831853;
+    var sum = vec3<f32>(0.0);
+    var weight_sum = 0.0;
+    for(var i = 0u; i < EMISSIVE_SPREAD_SAMPLES; i = i + 1u) {
+        // Every other sample sits on the inner ring, which counts more
+        let ring = select(1.0, 0.5, i % 2u == 0u);
+        let angle = jitter + f32(i) * 6.2831853 / f32(EMISSIVE_SPREAD_SAMPLES);
+        let offset = vec2<f32>(cos(angle), sin(angle)) * ring * lighting_globals.emissive_spread;
+        let weight = 1.5 - ring;
+        let emitted = textureSampleLevel(emissive_map, emissive_sampler, uv + offset / map_size, 0.0).rgb;
+        sum = sum + emitted * weight;
+        weight_sum = weight_sum + weight;
+    }
+    return sum / weight_sum * lighting_globals.emissive_light;
+}
+
+// Where a pixel of the view is in the world. Clip space has y pointing up, screen uvs have it pointing down.
+fn view_world_position(screen_uv: vec2<f32>) -> vec2<f32> {
+    let clip_position = vec4<f32>(screen_uv.x * 2.0 - 1.0, 1.0 - screen_uv.y * 2.0, 0.0, 1.0);
+    let world = view.inverse_view_proj * clip_position;
+    return world.xy / world.w;
+}
+
+// Where a world position is on the normal, emissive and baked maps
+fn map_uv(world_position: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(0.5 + world_position.x / lighting_globals.map_size.x, 0.5 - world_position.y / lighting_globals.map_size.y);
+}
+
+fn is_on_map(uv: vec2<f32>) -> bool {
+    return all(uv >= vec2<f32>(0.0)) && all(uv <= vec2<f32>(1.0));
+}
+
+// First pass, renders the light falling onto every pixel into the light texture, which may be smaller than the view
+@fragment
+fn accumulate(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let world_position = view_world_position(in.uv);
+
+    // The maps only cover the map, everything around it is flat and has no baked light
+    let uv = map_uv(world_position);
+    let on_map = is_on_map(uv);
+#ifdef NORMAL_MAP
+    let mapped_normal = normalize(textureSample(normal_map, normal_sampler, uv).rgb * 2.0 - 1.0);
+    let normal = select(vec3<f32>(0.0, 0.0, 1.0), mapped_normal, on_map);
+#endif
+#ifdef BAKED_LIGHTMAP
+    let baked_light = select(vec3<f32>(0.0), textureSample(baked_lightmap, baked_sampler, uv).rgb * BAKED_LIGHT_RANGE, on_map);
+#else
+    let baked_light = vec3<f32>(0.0);
+#endif
+
+    // Every visible light adds its color on top of the ambient light and the static lights
+    var light_sum = lighting_globals.ambient + baked_light;
+    for (var i = 0u; i < lighting_globals.light_count; i = i + 1u) {
+        let light = get_light(i);
+        if(light.is_active == 0u) {
+            continue;
+        }
+
+        let reach = light.radius + length(light.extent_a) + length(light.extent_b);
+        if(length(light.position - world_position) >= reach) {
+            continue;
+        }
+
+        var shading = 1.0;
+#ifdef NORMAL_MAP
+        shading = normal_shading(light, world_position, normal);
+#endif
+        light_sum = light_sum + light.color.rgb * light.intensity * shading * light_contribution(light, world_position);
+    }
+
+#ifdef EMISSIVE_MAP
+    if(on_map) {
+        light_sum = light_sum + emissive_spill(uv, world_position);
+    }
+#endif
+
+    return vec4<f32>(light_sum, 1.0);
+}
+
+// Second pass, multiplies the bilinearly upsampled light texture over the view
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(screen_texture, screen_sampler, in.uv);
+    let light_sum = textureSample(light_texture, light_sampler, in.uv).rgb;
+
+#ifdef EMISSIVE_MAP
+    // Sampled at full resolution, so glowing details stay sharp
+    let uv = map_uv(view_world_position(in.uv));
+    let emissive = select(vec3<f32>(0.0), textureSample(emissive_map, emissive_sampler, uv).rgb, is_on_map(uv));
+#else
+    let emissive = vec3<f32>(0.0);
+#endif
 
-    // Combine the original color and lit color, and preserve the alpha value
-    return color * light_intensity;
+    // Emissive pixels glow on top, no matter how dark it is around them
+    return vec4<f32>(mix(color.rgb, color.rgb * light_sum, lighting_globals.darkness) + emissive, color.a);
 }
@@ -32,6 +32,8 @@ pub struct Actions {
     pub cursor_position_raw: Option<Vec2>,
     pub world_cursor_position: Option<Vec2>,
     pub left_click: bool,
+    pub left_held: bool,
+    pub left_released: bool,
     pub ui_just_clicked: bool,
 }
 
@@ -87,4 +89,6 @@ pub fn update_mouse_click(mut actions: ResMut<Actions>, mouse_button_input: Res<
             actions.left_click = false;
         }
     }
+    actions.left_held = mouse_button_input.pressed(MouseButton::Left);
+    actions.left_released = mouse_button_input.just_released(MouseButton::Left);
 }
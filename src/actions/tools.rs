@@ -3,6 +3,9 @@ pub enum Tool {
     None,
     Select,
     BuildWall,
+    BuildPolygon,
     PlaceLight,
+    PlaceLineLight,
+    PlaceAreaLight,
     Delete
 }
\ No newline at end of file
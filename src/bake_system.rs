@@ -0,0 +1,154 @@
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    level::{LevelFile, LevelFilePath},
+    lighting::{
+        bake_lightmap, read_asset_image, BakedLighting, FalloffCurves, LightOccluder, LightSource, LightingTextures,
+    },
+    GameState,
+};
+
+pub struct BakeSystemPlugin;
+
+/// Bakes the static lights into a lightmap next to the level file, with Ctrl+B or the button in the tool bar
+impl Plugin for BakeSystemPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<BakeLighting>().add_systems(
+            (handle_bake_shortcut, bake_lighting, drop_stale_lightmap).chain().in_set(OnUpdate(GameState::Playing)),
+        );
+    }
+}
+
+pub struct BakeLighting;
+
+/// Where the lightmap of a level goes, next to the level file
+pub fn lightmap_path(level_path: &Path) -> PathBuf {
+    let stem = level_path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("level");
+    level_path.with_file_name(format!("{stem}_lightmap.ktx2"))
+}
+
+fn handle_bake_shortcut(keyboard_input: Res<Input<KeyCode>>, mut bake_events: EventWriter<BakeLighting>) {
+    let ctrl = keyboard_input.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    if ctrl && keyboard_input.just_pressed(KeyCode::B) {
+        bake_events.send(BakeLighting);
+    }
+}
+
+fn bake_lighting(
+    mut events: EventReader<BakeLighting>,
+    level_path: Res<LevelFilePath>,
+    lighting_textures: Res<LightingTextures>,
+    mut images: ResMut<Assets<Image>>,
+    light_q: Query<(Entity, &LightSource, &Transform)>,
+    wall_q: Query<(&LightOccluder, &Transform)>,
+    falloff_curves: Res<FalloffCurves>,
+    mut baked: ResMut<BakedLighting>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    let Some(source) = images.get(&lighting_textures.map) else {
+        warn!("Can't bake before the map is loaded");
+        return;
+    };
+
+    let static_lights: Vec<(Entity, &LightSource, &Transform)> =
+        light_q.iter().filter(|(_, light, _)| light.is_static).collect();
+    if static_lights.is_empty() {
+        info!("There are no static lights to bake, all lights are computed at runtime");
+        baked.lightmap = None;
+        baked.lights.clear();
+        return;
+    }
+    let lights: Vec<(LightSource, Transform)> =
+        static_lights.iter().map(|(_, light, transform)| (**light, **transform)).collect();
+    let occluders: Vec<(LightOccluder, Transform)> =
+        wall_q.iter().map(|(occluder, transform)| (occluder.clone(), *transform)).collect();
+
+    let size = source.texture_descriptor.size;
+    let normal_map = lighting_textures.normal_map.as_ref().and_then(|handle| images.get(handle));
+    let start = Instant::now();
+    let lightmap =
+        match bake_lightmap(UVec2::new(size.width, size.height), normal_map, &lights, &occluders, &falloff_curves) {
+            Ok(lightmap) => lightmap,
+            Err(err) => {
+                error!("Could not bake the normal map into the lightmap: {}", err);
+                return;
+            }
+        };
+    info!("Baked {} static lights in {:.1?}", lights.len(), start.elapsed());
+
+    let path = lightmap_path(&level_path.0);
+    match lightmap.save(&path) {
+        Ok(()) => info!("Saved lightmap to {:?}", path),
+        Err(err) => error!("Could not save lightmap to {:?}: {}", path, err),
+    }
+    baked.lightmap = Some(images.add(lightmap.to_image()));
+    baked.lights = static_lights.iter().map(|(entity, ..)| *entity).collect();
+}
+
+// Moving, editing or deleting a baked light makes the lightmap wrong. Until the next bake every light is computed at
+// runtime again, which also covers lights that were made static after the bake.
+fn drop_stale_lightmap(
+    mut baked: ResMut<BakedLighting>,
+    light_q: Query<(Entity, Ref<LightSource>, Ref<Transform>)>,
+    mut removed: RemovedComponents<LightSource>,
+) {
+    if baked.lightmap.is_none() {
+        return;
+    }
+
+    // Lights that were just spawned, e.g. by loading the level, are still the ones in the lightmap
+    let edited = light_q.iter().any(|(entity, light, transform)| {
+        let changed = (light.is_changed() && !light.is_added()) || (transform.is_changed() && !transform.is_added());
+        changed && baked.lights.contains(&entity)
+    });
+    let deleted = removed.iter().any(|entity| baked.lights.contains(&entity));
+    if edited || deleted {
+        warn!("A baked light changed, all lights are computed at runtime until the lighting is baked again");
+        baked.lightmap = None;
+        baked.lights.clear();
+    }
+}
+
+/// Bakes the static lights of a level file without starting the editor, for build scripts. The lightmap is written
+/// to `output`, or next to the level like the editor does. The background of the level and `normal_map` are relative
+/// to the asset directory `assets`, see [`read_asset_image`].
+pub fn bake_level(
+    level_path: &Path,
+    assets: &Path,
+    output: Option<&Path>,
+    normal_map: Option<&Path>,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let level = LevelFile::read(level_path)?;
+    if level.background.is_empty() {
+        return Err(format!("{level_path:?} has no background image to bake onto").into());
+    }
+
+    let size = read_asset_image(assets, Path::new(&level.background), true)?.texture_descriptor.size;
+    // Normals are read as they are, like in `setup_map`
+    let normal_map = normal_map.map(|path| read_asset_image(assets, path, false)).transpose()?;
+
+    let lights: Vec<(LightSource, Transform)> = level
+        .lights
+        .iter()
+        .filter(|light| light.light.is_static)
+        .map(|light| (light.light, light.transform()))
+        .collect();
+    let occluders: Vec<(LightOccluder, Transform)> =
+        level.walls.iter().map(|wall| (wall.occluder.clone(), wall.transform())).collect();
+    let falloff_curves = FalloffCurves { curves: level.falloff_curves };
+
+    let lightmap =
+        bake_lightmap(UVec2::new(size.width, size.height), normal_map.as_ref(), &lights, &occluders, &falloff_curves)?;
+    let path = output.map_or_else(|| lightmap_path(level_path), Path::to_path_buf);
+    lightmap.save(&path)?;
+    Ok(path)
+}
@@ -0,0 +1,97 @@
+//! Renders a saved level with the CPU version of the lighting shader, without a window or a GPU.
+//! Useful to preview a batch of levels or to diff the lighting in CI.
+
+use std::{error::Error, path::PathBuf, process};
+
+use bevy::prelude::*;
+use bevy_game::{
+    level::LevelFile,
+    lighting::{read_asset_image, render_lightmap, FalloffCurves, LightOccluder, LightSource},
+};
+
+const USAGE: &str = "Usage: render_level <level.ron> <output.png> [--assets <dir>] [--normal-map <normals.png>] \
+                     [--emissive-map <emissive.png>]";
+
+struct Options {
+    level: PathBuf,
+    output: PathBuf,
+    /// The background path in the level and the normal and emissive maps are relative to this
+    assets: PathBuf,
+    normal_map: Option<PathBuf>,
+    emissive_map: Option<PathBuf>,
+}
+
+fn main() {
+    let Some(options) = parse_args(std::env::args().skip(1)) else {
+        eprintln!("{USAGE}");
+        process::exit(2);
+    };
+
+    match render(&options) {
+        Ok(()) => println!("Rendered {} to {}", options.level.display(), options.output.display()),
+        Err(err) => {
+            eprintln!("Could not render {}: {}", options.level.display(), err);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Option<Options> {
+    let mut options = Options {
+        level: args.next()?.into(),
+        output: args.next()?.into(),
+        assets: PathBuf::from("assets"),
+        normal_map: None,
+        emissive_map: None,
+    };
+
+    while let Some(option) = args.next() {
+        let value = PathBuf::from(args.next()?);
+        match option.as_str() {
+            "--assets" => options.assets = value,
+            "--normal-map" => options.normal_map = Some(value),
+            "--emissive-map" => options.emissive_map = Some(value),
+            _ => return None,
+        }
+    }
+    Some(options)
+}
+
+fn render(options: &Options) -> Result<(), Box<dyn Error>> {
+    let level = LevelFile::read(&options.level)?;
+    if level.background.is_empty() {
+        return Err("the level has no background image".into());
+    }
+
+    let assets = &options.assets;
+    let source = read_asset_image(assets, level.background.as_ref(), true)?;
+    let normal_map = options.normal_map.as_deref().map(|path| read_asset_image(assets, path, false)).transpose()?;
+    let emissive_map = options.emissive_map.as_deref().map(|path| read_asset_image(assets, path, true)).transpose()?;
+
+    let lights: Vec<(LightSource, Transform)> =
+        level.lights.iter().map(|light| (light.light, light.transform())).collect();
+    let occluders: Vec<(LightOccluder, Transform)> =
+        level.walls.iter().map(|wall| (wall.occluder.clone(), wall.transform())).collect();
+    let falloff_curves = FalloffCurves { curves: level.falloff_curves };
+
+    let lit = render_lightmap(
+        &source,
+        normal_map.as_ref(),
+        emissive_map.as_ref(),
+        &lights,
+        &occluders,
+        &falloff_curves,
+        &level.illumination,
+    )?;
+
+    let size = lit.texture_descriptor.size;
+    image::save_buffer_with_format(
+        &options.output,
+        &lit.data,
+        size.width,
+        size.height,
+        image::ColorType::Rgba8,
+        image::ImageFormat::Png,
+    )?;
+    Ok(())
+}
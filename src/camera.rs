@@ -1,5 +1,5 @@
+use bevy::core_pipeline::tonemapping::DebandDither;
 use bevy::prelude::*;
-use bevy::render::camera::RenderTarget;
 use bevy_mod_picking::PickingCameraBundle;
 use bevy_mod_raycast::DefaultRaycastingPlugin;
 use bevy_mod_raycast::RaycastMesh;
@@ -8,7 +8,7 @@ use bevy_pancam::*;
 use crate::components::*;
 
 use crate::GameState;
-use crate::lighting::CameraSet;
+use crate::lighting::{CameraSet, LightingCamera};
 
 #[derive(Component)]
 pub struct MainCamera;
@@ -23,15 +23,19 @@ impl Plugin for CameraPlugin {
     }
 }
 
-pub fn setup_camera(mut commands: &mut Commands,target: RenderTarget) {
+pub fn setup_camera(commands: &mut Commands) {
     commands.spawn(Camera2dBundle {
         camera: Camera{ 
+            // The lights can add up to more than white, see `HdrSettings`
+            hdr: true,
             ..default()
         },
+        deband_dither: DebandDither::Enabled,
         ..Default::default()
     })
     .insert(PickingCameraBundle::default())
     .insert(MainCamera)
+    .insert(LightingCamera)
     // .insert(UiCameraConfig { show_ui: false })
     .insert(    PanCam {
         grab_buttons: vec![MouseButton::Left, MouseButton::Middle], // which buttons should drag the camera
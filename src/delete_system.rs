@@ -1,4 +1,4 @@
-use crate::{components::{Deleteable, RaycastSet}, GameState, actions::{Actions, Tool}};
+use crate::{components::{Deleteable, RaycastSet}, GameState, actions::{Actions, Tool}, history::{Edit, EditHistory, EditObject}, lighting::{LightAnimation, LightOccluder, LightSource}, wall::TracedWall};
 use bevy::{prelude::*, transform::{self, commands}, sprite::Mesh2dHandle};
 use bevy_mod_picking::{DefaultPickingPlugins, PickingEvent};
 use bevy_mod_raycast::{
@@ -20,15 +20,36 @@ pub fn current_tool_is_delete(actions: Res<Actions>) -> bool {
     actions.current_tool() == Some(Tool::Delete)
 }
 
-pub fn print_events(mut events: EventReader<PickingEvent>, mut commands: Commands, deletable_q: Query<&Deleteable>) {
+// Everything an undo needs to bring a deleted wall or light back
+type DeletableQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static Transform,
+        Option<&'static LightOccluder>,
+        Option<&'static LightSource>,
+        Option<&'static LightAnimation>,
+        Option<&'static TracedWall>,
+    ),
+    With<Deleteable>,
+>;
+
+pub fn print_events(mut events: EventReader<PickingEvent>, mut commands: Commands, mut history: ResMut<EditHistory>, deletable_q: DeletableQuery) {
     for event in events.iter() {
         match event {
             PickingEvent::Selection(e) => info!("A selection event happened: {:?}", e),
             PickingEvent::Hover(e) => info!("Egads! A hover event!? {:?}", e),
             PickingEvent::Clicked(e) => {
                 match deletable_q.get(*e) {
-                    Ok(_) => {
+                    Ok((transform, occluder, light, animation, traced)) => {
                         info!("A click event happened: {:?}", e);
+                        if let Some(mut object) = EditObject::from_components(transform, occluder, light, animation) {
+                            // Undoing brings it back as a traced wall, so the next trace still replaces it
+                            if let EditObject::Wall(wall) = &mut object {
+                                wall.traced = traced.is_some();
+                            }
+                            history.record(Edit::Delete { entity: *e, object });
+                        }
                         commands.entity(*e).remove::<ShapeBundle>();
                         commands.entity(*e).despawn();
                     },
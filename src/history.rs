@@ -0,0 +1,279 @@
+use bevy::prelude::*;
+
+use crate::{
+    level::{fall_back_to_segment_shadows, LightData, WallData},
+    lighting::{BakedLighting, GlobalIllumination, LightAnimation, LightOccluder, LightSource},
+    lightplacing_system::{light_fill, light_path, spawn_light},
+    wall::{spawn_wall, wall_fill, wall_path},
+    GameState,
+};
+
+pub struct HistoryPlugin;
+
+/// Keeps track of every edit made with the editing tools so they can be undone with Ctrl+Z
+/// and redone with Ctrl+Shift+Z
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditHistory>().add_system(handle_undo_redo.in_set(OnUpdate(GameState::Playing)));
+    }
+}
+
+/// Everything needed to bring back a wall or light, see [`WallData`] and [`LightData`]
+#[derive(Debug, Clone)]
+pub enum EditObject {
+    Wall(WallData),
+    Light(LightData),
+}
+
+impl EditObject {
+    pub fn from_components(
+        transform: &Transform,
+        occluder: Option<&LightOccluder>,
+        light: Option<&LightSource>,
+        animation: Option<&LightAnimation>,
+    ) -> Option<Self> {
+        match (occluder, light) {
+            (Some(occluder), _) => Some(EditObject::Wall(WallData::from_transform(transform, occluder))),
+            (None, Some(light)) => Some(EditObject::Light(LightData::from_transform(transform, light, animation))),
+            (None, None) => None,
+        }
+    }
+
+    fn spawn(&self, commands: &mut Commands) -> Entity {
+        match self {
+            EditObject::Wall(wall) => spawn_wall(commands, wall),
+            EditObject::Light(light) => spawn_light(commands, light),
+        }
+    }
+
+    // Overwrites the components of an existing entity with this state
+    fn apply(&self, entity: Entity, commands: &mut Commands, transform_q: &mut Query<&mut Transform>) {
+        let (position, rotation) = match self {
+            EditObject::Wall(wall) => {
+                commands.entity(entity).insert((
+                    wall.occluder.clone(),
+                    wall_path(&wall.occluder),
+                    wall_fill(&wall.occluder),
+                ));
+                (wall.position, Quat::from_rotation_z(wall.rotation))
+            }
+            EditObject::Light(light) => {
+                let mut entity_commands = commands.entity(entity);
+                entity_commands.insert((
+                    LightSource { position: light.position, ..light.light },
+                    light_path(&light.light),
+                    light_fill(&light.light),
+                ));
+                match &light.animation {
+                    Some(animation) => entity_commands.insert(animation.clone()),
+                    None => entity_commands.remove::<LightAnimation>(),
+                };
+                (light.position, Quat::from_rotation_z(light.rotation))
+            }
+        };
+
+        if let Ok(mut transform) = transform_q.get_mut(entity) {
+            transform.translation.x = position.x;
+            transform.translation.y = position.y;
+            transform.rotation = rotation;
+        }
+    }
+}
+
+/// A single reversible edit
+#[derive(Debug, Clone)]
+pub enum Edit {
+    Create {
+        entity: Entity,
+        object: EditObject,
+    },
+    Delete {
+        entity: Entity,
+        object: EditObject,
+    },
+    Modify {
+        entity: Entity,
+        before: EditObject,
+        after: EditObject,
+    },
+    /// Several edits that are undone and redone together, e.g. moving multiple selected objects.
+    /// Every edit in a group has to be about a different entity.
+    Group(Vec<Edit>),
+}
+
+impl Edit {
+    fn replace_entity(&mut self, old: Entity, new: Entity) {
+        match self {
+            Edit::Create { entity, .. } | Edit::Delete { entity, .. } | Edit::Modify { entity, .. } => {
+                if *entity == old {
+                    *entity = new;
+                }
+            }
+            Edit::Group(edits) => {
+                for edit in edits.iter_mut() {
+                    edit.replace_entity(old, new);
+                }
+            }
+        }
+    }
+
+    // What exists again once the edit is undone, or redone
+    fn restored(&self, undone: bool) -> Vec<&EditObject> {
+        match (self, undone) {
+            (Edit::Delete { object, .. }, true) | (Edit::Create { object, .. }, false) => vec![object],
+            (Edit::Create { .. }, true) | (Edit::Delete { .. }, false) => Vec::new(),
+            (Edit::Modify { before, .. }, true) => vec![before],
+            (Edit::Modify { after, .. }, false) => vec![after],
+            (Edit::Group(edits), _) => edits.iter().flat_map(|edit| edit.restored(undone)).collect(),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct EditHistory {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl EditHistory {
+    /// Records an edit that was just made. This throws away everything that could have been redone.
+    pub fn record(&mut self, edit: Edit) {
+        self.undo.push(edit);
+        self.redo.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /// Returns the edit that was undone, if there was one. Baked lights that get respawned stay in `baked`.
+    pub fn undo(
+        &mut self,
+        commands: &mut Commands,
+        transform_q: &mut Query<&mut Transform>,
+        baked: &mut BakedLighting,
+    ) -> Option<&Edit> {
+        let edit = self.undo.pop()?;
+        let edit = self.revert(edit, commands, transform_q, baked);
+        self.redo.push(edit);
+        self.redo.last()
+    }
+
+    /// Returns the edit that was redone, if there was one. Baked lights that get respawned stay in `baked`.
+    pub fn redo(
+        &mut self,
+        commands: &mut Commands,
+        transform_q: &mut Query<&mut Transform>,
+        baked: &mut BakedLighting,
+    ) -> Option<&Edit> {
+        let edit = self.redo.pop()?;
+        let edit = self.reapply(edit, commands, transform_q, baked);
+        self.undo.push(edit);
+        self.undo.last()
+    }
+
+    fn revert(
+        &mut self,
+        edit: Edit,
+        commands: &mut Commands,
+        transform_q: &mut Query<&mut Transform>,
+        baked: &mut BakedLighting,
+    ) -> Edit {
+        match edit {
+            Edit::Create { entity, object } => {
+                commands.entity(entity).despawn_recursive();
+                Edit::Create { entity, object }
+            }
+            Edit::Delete { entity, object } => {
+                let respawned = object.spawn(commands);
+                self.replace_entity(entity, respawned, baked);
+                Edit::Delete { entity: respawned, object }
+            }
+            Edit::Modify { entity, before, after } => {
+                before.apply(entity, commands, transform_q);
+                Edit::Modify { entity, before, after }
+            }
+            Edit::Group(edits) => {
+                let mut reverted: Vec<Edit> =
+                    edits.into_iter().rev().map(|edit| self.revert(edit, commands, transform_q, baked)).collect();
+                reverted.reverse();
+                Edit::Group(reverted)
+            }
+        }
+    }
+
+    fn reapply(
+        &mut self,
+        edit: Edit,
+        commands: &mut Commands,
+        transform_q: &mut Query<&mut Transform>,
+        baked: &mut BakedLighting,
+    ) -> Edit {
+        match edit {
+            Edit::Create { entity, object } => {
+                let respawned = object.spawn(commands);
+                self.replace_entity(entity, respawned, baked);
+                Edit::Create { entity: respawned, object }
+            }
+            Edit::Delete { entity, object } => {
+                commands.entity(entity).despawn_recursive();
+                Edit::Delete { entity, object }
+            }
+            Edit::Modify { entity, before, after } => {
+                after.apply(entity, commands, transform_q);
+                Edit::Modify { entity, before, after }
+            }
+            Edit::Group(edits) => {
+                Edit::Group(edits.into_iter().map(|edit| self.reapply(edit, commands, transform_q, baked)).collect())
+            }
+        }
+    }
+
+    // Respawned entities get a new id, so older edits of the same object have to point to the new one, and so does
+    // the lightmap if the object is a baked light
+    fn replace_entity(&mut self, old: Entity, new: Entity, baked: &mut BakedLighting) {
+        for edit in self.undo.iter_mut().chain(self.redo.iter_mut()) {
+            edit.replace_entity(old, new);
+        }
+        if baked.lights.remove(&old) {
+            baked.lights.insert(new);
+        }
+    }
+}
+
+// Undoing and redoing can bring back walls and lights the distance field can't show
+fn handle_undo_redo(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut history: ResMut<EditHistory>,
+    mut illumination: ResMut<GlobalIllumination>,
+    mut baked: ResMut<BakedLighting>,
+    mut commands: Commands,
+    mut transform_q: Query<&mut Transform>,
+) {
+    let ctrl = keyboard_input.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    if !ctrl || !keyboard_input.just_pressed(KeyCode::Z) {
+        return;
+    }
+
+    let redo = keyboard_input.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    let edit = if redo {
+        history.redo(&mut commands, &mut transform_q, &mut baked)
+    } else {
+        history.undo(&mut commands, &mut transform_q, &mut baked)
+    };
+    let Some(edit) = edit else {
+        return;
+    };
+
+    let restored = edit.restored(!redo);
+    let occluders = restored.iter().filter_map(|object| match object {
+        EditObject::Wall(wall) => Some(&wall.occluder),
+        EditObject::Light(_) => None,
+    });
+    let lights = restored.iter().filter_map(|object| match object {
+        EditObject::Light(light) => Some(&light.light),
+        EditObject::Wall(_) => None,
+    });
+    fall_back_to_segment_shadows(&mut illumination, occluders, lights);
+}
@@ -0,0 +1,301 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bake_system::lightmap_path,
+    history::EditHistory,
+    lighting::{
+        BakedLighting, DistanceField, FalloffCurves, GlobalIllumination, LightAnimation, LightOccluder, LightSource,
+        LightingTextures, OccluderTracing, ShadowMode,
+    },
+    lightplacing_system::{spawn_light, PreliminaryLight},
+    map::{set_lit_map, MapBackground},
+    wall::{spawn_wall, PreliminaryPolygon, PreliminaryWall, TracedWall},
+    GameState,
+};
+
+pub struct LevelPlugin;
+
+/// Saves and loads everything placed in the editor (walls, lights and the map background)
+/// Save with Ctrl+S, load with Ctrl+O or the buttons in the tool bar
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LevelFilePath>()
+            .add_event::<SaveLevel>()
+            .add_event::<LoadLevel>()
+            .add_systems((handle_level_shortcuts, save_level, load_level).chain().in_set(OnUpdate(GameState::Playing)));
+    }
+}
+
+/// Switches from distance field to segment shadows if the field can't show these occluders and lights, see
+/// [`DistanceField::supports`]. For walls and lights that show up without going through the settings panel's check.
+pub fn fall_back_to_segment_shadows<'a>(
+    illumination: &mut GlobalIllumination,
+    occluders: impl IntoIterator<Item = &'a LightOccluder>,
+    lights: impl IntoIterator<Item = &'a LightSource>,
+) {
+    if matches!(illumination.shadows, ShadowMode::DistanceField { .. }) && !DistanceField::supports(occluders, lights) {
+        warn!(
+            "Distance field shadows can't show the light layers, wall heights and translucent walls of this level, \
+             using segment shadows"
+        );
+        illumination.shadows = ShadowMode::Segments;
+    }
+}
+
+/// Where the level gets saved to and loaded from
+#[derive(Resource)]
+pub struct LevelFilePath(pub PathBuf);
+
+impl Default for LevelFilePath {
+    fn default() -> Self {
+        Self(PathBuf::from("assets/levels/level.ron"))
+    }
+}
+
+pub struct SaveLevel;
+
+pub struct LoadLevel;
+
+/// The on disk format of a level
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LevelFile {
+    /// Asset path of the map image, e.g. "textures/dungeon.png"
+    pub background: String,
+    pub walls: Vec<WallData>,
+    pub lights: Vec<LightData>,
+    /// See [`FalloffCurves`]
+    #[serde(default)]
+    pub falloff_curves: Vec<Vec<f32>>,
+    #[serde(default)]
+    pub illumination: GlobalIllumination,
+    /// How the map image gets traced into walls
+    #[serde(default)]
+    pub occluder_tracing: OccluderTracing,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WallData {
+    /// Translation of the wall, for rects this is the top left corner
+    pub position: Vec2,
+    /// Rotation around the z axis in radians
+    #[serde(default)]
+    pub rotation: f32,
+    pub occluder: LightOccluder,
+    /// Traced from the map image, the next trace replaces these walls, see [`TracedWall`]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub traced: bool,
+}
+
+impl WallData {
+    pub fn from_transform(transform: &Transform, occluder: &LightOccluder) -> Self {
+        Self {
+            position: transform.translation.truncate(),
+            rotation: transform.rotation.to_euler(EulerRot::XYZ).2,
+            occluder: occluder.clone(),
+            traced: false,
+        }
+    }
+
+    // Walls are drawn above the map and lights
+    pub fn transform(&self) -> Transform {
+        Transform::from_translation(self.position.extend(1.0)).with_rotation(Quat::from_rotation_z(self.rotation))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LightData {
+    /// Translation of the light, [`LightSource::position`] isn't saved and gets set from this
+    pub position: Vec2,
+    /// Rotation around the z axis in radians, spotlights shine along the rotated x axis
+    #[serde(default)]
+    pub rotation: f32,
+    pub light: LightSource,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub animation: Option<LightAnimation>,
+}
+
+impl LightData {
+    pub fn from_transform(transform: &Transform, light: &LightSource, animation: Option<&LightAnimation>) -> Self {
+        Self {
+            position: transform.translation.truncate(),
+            rotation: transform.rotation.to_euler(EulerRot::XYZ).2,
+            light: *light,
+            animation: animation.cloned(),
+        }
+    }
+
+    // Lights are drawn below walls
+    pub fn transform(&self) -> Transform {
+        Transform::from_translation(self.position.extend(0.0)).with_rotation(Quat::from_rotation_z(self.rotation))
+    }
+}
+
+impl LevelFile {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        Ok(ron::from_str(&contents)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, contents)?;
+        Ok(())
+    }
+}
+
+fn handle_level_shortcuts(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut save_events: EventWriter<SaveLevel>,
+    mut load_events: EventWriter<LoadLevel>,
+) {
+    let ctrl = keyboard_input.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    if !ctrl {
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::S) {
+        save_events.send(SaveLevel);
+    }
+    if keyboard_input.just_pressed(KeyCode::O) {
+        load_events.send(LoadLevel);
+    }
+}
+
+// Walls and lights that are still being drawn aren't saved
+type SavedWallQuery<'w, 's> = Query<
+    'w,
+    's,
+    (&'static LightOccluder, &'static Transform, Option<&'static TracedWall>),
+    (Without<PreliminaryWall>, Without<PreliminaryPolygon>),
+>;
+
+fn save_level(
+    mut events: EventReader<SaveLevel>,
+    path: Res<LevelFilePath>,
+    asset_server: Res<AssetServer>,
+    background_q: Query<&Handle<Image>, With<MapBackground>>,
+    wall_q: SavedWallQuery,
+    light_q: Query<(&LightSource, &Transform, Option<&LightAnimation>), Without<PreliminaryLight>>,
+    falloff_curves: Res<FalloffCurves>,
+    illumination: Res<GlobalIllumination>,
+    occluder_tracing: Res<OccluderTracing>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    let background = background_q
+        .get_single()
+        .ok()
+        .and_then(|handle| asset_server.get_handle_path(handle))
+        .map(|asset_path| asset_path.path().to_string_lossy().replace('\\', "/"))
+        .unwrap_or_default();
+
+    let level = LevelFile {
+        background,
+        walls: wall_q
+            .iter()
+            .map(|(occluder, transform, traced)| WallData {
+                traced: traced.is_some(),
+                ..WallData::from_transform(transform, occluder)
+            })
+            .collect(),
+        lights: light_q
+            .iter()
+            .map(|(light, transform, animation)| LightData::from_transform(transform, light, animation))
+            .collect(),
+        falloff_curves: falloff_curves.curves.clone(),
+        illumination: illumination.clone(),
+        occluder_tracing: occluder_tracing.clone(),
+    };
+
+    match level.write(&path.0) {
+        Ok(()) => info!("Saved level to {:?}", path.0),
+        Err(err) => error!("Could not save level to {:?}: {}", path.0, err),
+    }
+}
+
+fn load_level(
+    mut commands: Commands,
+    mut events: EventReader<LoadLevel>,
+    path: Res<LevelFilePath>,
+    asset_server: Res<AssetServer>,
+    mut background_q: Query<&mut Handle<Image>, With<MapBackground>>,
+    mut lighting_textures: ResMut<LightingTextures>,
+    placed_q: Query<Entity, Or<(With<LightOccluder>, With<LightSource>)>>,
+    mut history: ResMut<EditHistory>,
+    mut falloff_curves: ResMut<FalloffCurves>,
+    mut illumination: ResMut<GlobalIllumination>,
+    mut baked: ResMut<BakedLighting>,
+    mut occluder_tracing: ResMut<OccluderTracing>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    let level = match LevelFile::read(&path.0) {
+        Ok(level) => level,
+        Err(err) => {
+            error!("Could not load level from {:?}: {}", path.0, err);
+            return;
+        }
+    };
+
+    for entity in placed_q.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    // The recorded edits point to entities that don't exist anymore
+    history.clear();
+
+    falloff_curves.curves = level.falloff_curves;
+    *illumination = level.illumination;
+    *occluder_tracing = level.occluder_tracing;
+
+    // The editor doesn't save levels like this, but they can be written by hand
+    fall_back_to_segment_shadows(
+        &mut illumination,
+        level.walls.iter().map(|wall| &wall.occluder),
+        level.lights.iter().map(|light| &light.light),
+    );
+
+    for wall in level.walls.iter() {
+        spawn_wall(&mut commands, wall);
+    }
+
+    // The lightmap next to the level file was baked from its static lights
+    baked.lights.clear();
+    for light in level.lights.iter() {
+        let entity = spawn_light(&mut commands, light);
+        if light.light.is_static {
+            baked.lights.insert(entity);
+        }
+    }
+
+    // The normal and emissive maps of the old background don't fit the new one
+    if !level.background.is_empty() {
+        let image: Handle<Image> = asset_server.load(level.background.as_str());
+        for mut background in background_q.iter_mut() {
+            *background = image.clone();
+        }
+        set_lit_map(&mut lighting_textures, &asset_server, image, Path::new(&level.background));
+    }
+
+    // The lightmap baked for this level, if there is one. The asset server wants paths relative to `assets`.
+    let lightmap = lightmap_path(&path.0);
+    baked.lightmap = match lightmap.strip_prefix("assets") {
+        Ok(asset_path) if lightmap.exists() => Some(asset_server.load(asset_path.to_path_buf())),
+        _ => None,
+    };
+
+    info!("Loaded level from {:?}", path.0);
+}
@@ -3,7 +3,7 @@ mod audio;
 mod loading;
 mod menu;
 mod player;
-mod lighting;
+pub mod lighting;
 mod camera;
 mod map;
 mod ui;
@@ -11,11 +11,21 @@ mod wall;
 mod components;
 mod delete_system;
 mod lightplacing_system;
+pub mod level;
+mod history;
+mod select_system;
+mod bake_system;
+mod trace_system;
 
 use crate::actions::ActionsPlugin;
+use crate::bake_system::BakeSystemPlugin;
 use crate::audio::InternalAudioPlugin;
+use crate::level::LevelPlugin;
+use crate::history::HistoryPlugin;
 use crate::loading::LoadingPlugin;
 use crate::menu::MenuPlugin;
+use crate::select_system::SelectSystemPlugin;
+use crate::trace_system::TraceSystemPlugin;
 use crate::wall::WallBuildingPlugin;
 
 use crate::map::MapPlugin;
@@ -28,9 +38,11 @@ use bevy::prelude::*;
 use bevy_prototype_lyon::prelude::ShapePlugin;
 use camera::CameraPlugin;
 use delete_system::DeleteSystemPlugin;
-use lighting::LightingPostprocessPlugin;
+use lighting::{LightAnimationPlugin, LightingPostprocessPlugin};
 use lightplacing_system::LightPlaceSystem;
 
+pub use bake_system::bake_level;
+
 // This example game uses States to separate logic
 // See https://bevy-cheatbook.github.io/programming/states.html
 // Or https://github.com/bevyengine/bevy/blob/main/examples/ecs/state.rs
@@ -51,6 +63,7 @@ impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
         app.add_state::<GameState>()
             .add_plugin(LightingPostprocessPlugin)
+            .add_plugin(LightAnimationPlugin)
             .add_plugin(DeleteSystemPlugin)
             .add_plugin(LightPlaceSystem)
             .add_plugin(UiPlugin)
@@ -58,6 +71,11 @@ impl Plugin for GamePlugin {
             .add_plugin(LoadingPlugin)
             .add_plugin(WallBuildingPlugin)
             .add_plugin(MapPlugin)
+            .add_plugin(LevelPlugin)
+            .add_plugin(BakeSystemPlugin)
+            .add_plugin(TraceSystemPlugin)
+            .add_plugin(HistoryPlugin)
+            .add_plugin(SelectSystemPlugin)
             .add_plugin(CameraPlugin)
             .add_plugin(MenuPlugin)
             .add_plugin(ActionsPlugin)
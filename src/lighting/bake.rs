@@ -0,0 +1,207 @@
+use std::{error::Error, fs, path::Path, thread};
+
+use bevy::utils::HashSet;
+
+use bevy::{
+    prelude::*,
+    render::{
+        color::SrgbColorSpace,
+        extract_resource::ExtractResource,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+
+use super::{
+    cpu_lighting::{check_format, gpu_lights, occlusion, sample_normal},
+    direct_light, FalloffCurves, LightOccluder, LightSource, ShadowMode, UnsupportedFormat,
+};
+
+/// Baked lightmaps store the light divided by this, so a light sum of up to this fits into a PNG.
+/// Keep in sync with `lighting_pass.wgsl`.
+pub const BAKED_LIGHT_RANGE: f32 = 4.0;
+
+/// The lightmap of the static lights. While there is one, the lights baked into it are skipped by the shader,
+/// their light comes from the lightmap instead. Static lights that weren't baked are still computed at runtime.
+#[derive(Resource, ExtractResource, Default, Clone)]
+pub struct BakedLighting {
+    pub lightmap: Option<Handle<Image>>,
+    /// The lights in the lightmap. Once one of them changes the lightmap is out of date and gets dropped until
+    /// the next bake, see `drop_stale_lightmap`.
+    pub lights: HashSet<Entity>,
+}
+
+/// The light of the static lights at every pixel of the map, see [`bake_lightmap`]
+#[derive(Clone, Debug)]
+pub struct BakedLightmap {
+    pub width: u32,
+    pub height: u32,
+    /// Linear light, row by row starting at the top
+    pub light: Vec<Vec3>,
+}
+
+/// Renders the light of `lights` into a lightmap the size of the map, one texel per world unit and centered on
+/// the world origin like in `setup_map`. Ambient light isn't included, it's added at runtime. The normal map has to be
+/// 8 bit RGBA, see [`super::render_lightmap`].
+///
+/// This costs pixels × lights × occluder segments along the shadow rays, so every core bakes a part of the rows.
+pub fn bake_lightmap(
+    map_size: UVec2,
+    normal_map: Option<&Image>,
+    lights: &[(LightSource, Transform)],
+    occluders: &[(LightOccluder, Transform)],
+    falloff_curves: &FalloffCurves,
+) -> Result<BakedLightmap, UnsupportedFormat> {
+    if let Some(normal_map) = normal_map {
+        check_format(normal_map)?;
+    }
+
+    let lights = gpu_lights(lights);
+    // Baking has all the time it needs, so it always uses the exact segment shadows
+    let occlusion = occlusion(occluders, ShadowMode::Segments);
+    let curves = falloff_curves.resampled();
+
+    let size = map_size.as_vec2();
+    let half_size = size / 2.0;
+    let bake_row = |y: u32| -> Vec<Vec3> {
+        (0..map_size.x)
+            .map(|x| {
+                let (x, y) = (x as f32, y as f32);
+                let world_position = Vec2::new(x + 0.5 - half_size.x, half_size.y - y - 0.5);
+                let normal = normal_map.map(|normal_map| sample_normal(normal_map, Vec2::new(x + 0.5, y + 0.5) / size));
+                direct_light(normal, world_position, &lights, &occlusion, &curves)
+            })
+            .collect()
+    };
+
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get()) as u32;
+    let rows_per_thread = (map_size.y + threads - 1) / threads.max(1);
+    let light = thread::scope(|scope| {
+        let bakers: Vec<_> = (0..threads)
+            .map(|thread| {
+                let rows = (thread * rows_per_thread)..((thread + 1) * rows_per_thread).min(map_size.y);
+                scope.spawn(move || rows.flat_map(bake_row).collect::<Vec<Vec3>>())
+            })
+            .collect();
+        bakers.into_iter().flat_map(|baker| baker.join().unwrap()).collect()
+    });
+
+    Ok(BakedLightmap { width: map_size.x, height: map_size.y, light })
+}
+
+impl BakedLightmap {
+    /// A half float texture, like the one saved to KTX2
+    pub fn to_image(&self) -> Image {
+        Image::new(
+            Extent3d { width: self.width, height: self.height, ..default() },
+            TextureDimension::D2,
+            self.half_float_texels(),
+            TextureFormat::Rgba16Float,
+        )
+    }
+
+    /// Saves the lightmap as a PNG (8 bit sRGB, clamped to [`BAKED_LIGHT_RANGE`]) or as a KTX2 (half floats),
+    /// depending on the extension of `path`
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("png") => {
+                let to_u8 = |value: f32| {
+                    ((value / BAKED_LIGHT_RANGE).clamp(0.0, 1.0).linear_to_nonlinear_srgb() * 255.0).round() as u8
+                };
+                let data: Vec<u8> = self
+                    .light
+                    .iter()
+                    .flat_map(|light| [to_u8(light.x), to_u8(light.y), to_u8(light.z), u8::MAX])
+                    .collect();
+                image::save_buffer_with_format(
+                    path,
+                    &data,
+                    self.width,
+                    self.height,
+                    image::ColorType::Rgba8,
+                    image::ImageFormat::Png,
+                )?;
+            }
+            Some("ktx2") => fs::write(path, self.to_ktx2())?,
+            _ => return Err(format!("Lightmaps can only be saved as png or ktx2, not {path:?}").into()),
+        }
+        Ok(())
+    }
+
+    fn half_float_texels(&self) -> Vec<u8> {
+        self.light
+            .iter()
+            .flat_map(|light| (*light / BAKED_LIGHT_RANGE).extend(1.0).to_array())
+            .flat_map(|value| half_float_bits(value).to_le_bytes())
+            .collect()
+    }
+
+    // A single level, uncompressed R16G16B16A16_SFLOAT KTX2 file
+    fn to_ktx2(&self) -> Vec<u8> {
+        const VK_FORMAT_R16G16B16A16_SFLOAT: u32 = 97;
+        const HEADER_SIZE: u32 = 80;
+        const LEVEL_INDEX_SIZE: u32 = 24;
+        const DFD_SIZE: u32 = 4 + 24 + 4 * 16;
+        // Level data has to be aligned to the 8 byte texel size
+        const DATA_OFFSET: u32 = (HEADER_SIZE + LEVEL_INDEX_SIZE + DFD_SIZE).div_ceil(8) * 8;
+
+        let texels = self.half_float_texels();
+        let mut file = Vec::with_capacity(DATA_OFFSET as usize + texels.len());
+        let push_u32 = |file: &mut Vec<u8>, value: u32| file.extend_from_slice(&value.to_le_bytes());
+
+        file.extend_from_slice(&[0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n']);
+        // Format, type size, width, height, depth, layers, faces, levels and supercompression
+        for value in [VK_FORMAT_R16G16B16A16_SFLOAT, 2, self.width, self.height, 0, 0, 1, 1, 0] {
+            push_u32(&mut file, value);
+        }
+        // Data format descriptor, no key/value data and no supercompression global data
+        for value in [HEADER_SIZE + LEVEL_INDEX_SIZE, DFD_SIZE, 0, 0, 0, 0, 0, 0] {
+            push_u32(&mut file, value);
+        }
+        // Level index: offset, length and uncompressed length as u64
+        for value in [DATA_OFFSET as u64, texels.len() as u64, texels.len() as u64] {
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+
+        // Basic data format descriptor for linear RGBA half floats
+        push_u32(&mut file, DFD_SIZE);
+        push_u32(&mut file, 0);
+        push_u32(&mut file, 2 | ((DFD_SIZE - 4) << 16));
+        // RGBSDA color model, BT.709 primaries, linear transfer function, straight alpha
+        file.extend_from_slice(&[1, 1, 1, 0]);
+        // Texel block dimensions and bytes per plane
+        file.extend_from_slice(&[0, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0]);
+        for (index, channel) in [0u32, 1, 2, 15].into_iter().enumerate() {
+            // Bit offset, bit length - 1 and channel id with the float and signed flags
+            push_u32(&mut file, (index as u32 * 16) | (15 << 16) | ((channel | 0xC0) << 24));
+            push_u32(&mut file, 0);
+            push_u32(&mut file, (-1.0f32).to_bits());
+            push_u32(&mut file, 1.0f32.to_bits());
+        }
+
+        file.resize(DATA_OFFSET as usize, 0);
+        file.extend_from_slice(&texels);
+        file
+    }
+}
+
+// Rounds towards zero, tiny values flush to zero and huge ones become infinity
+fn half_float_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127 + 15;
+    let mantissa = ((bits >> 13) & 0x3FF) as u16;
+    if value.is_nan() {
+        return sign | 0x7E00;
+    }
+    if exponent <= 0 {
+        return sign;
+    }
+    if exponent >= 31 {
+        return sign | 0x7C00;
+    }
+    sign | ((exponent as u16) << 10) | mantissa
+}
@@ -1,9 +1,134 @@
 use bevy::prelude::Component;
 
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Component, Default, Clone, Copy, Debug)]
+use super::{all_light_layers, ALL_LIGHT_LAYERS};
+
+/// How many segments a circle occluder is approximated with
+pub const CIRCLE_SEGMENTS: usize = 16;
+
+/// Outline of an occluder, in the local space of its `Transform`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum OccluderShape {
+    /// Spans from the origin to (width, -height), so the translation is the top left corner
+    Rect { width: f32, height: f32 },
+    /// Closed outline, the last point connects back to the first
+    Polygon(Vec<Vec2>),
+    /// Open chain of line segments
+    Polyline(Vec<Vec2>),
+    Circle { radius: f32 },
+}
+
+impl Default for OccluderShape {
+    fn default() -> Self {
+        OccluderShape::Rect { width: 0.0, height: 0.0 }
+    }
+}
+
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct LightOccluder {
-    pub width: f32,
-    pub height: f32,
-}
\ No newline at end of file
+    pub shape: OccluderShape,
+    /// Bitmask of light layers, only lights sharing a layer with the occluder are blocked by it
+    #[serde(default = "all_light_layers")]
+    pub layers: u32,
+    /// How far the occluder reaches above the map. Lights hanging higher shine over it, its shadow ends where the
+    /// line from the light over its top hits the map. Without a height it blocks lights no matter how high they hang.
+    #[serde(default)]
+    pub height: Option<f32>,
+    /// Tints the light passing through, like stained glass or water. Only matters if `opacity` is below 1.
+    #[serde(default = "white")]
+    pub transmittance: Color,
+    /// How much light the occluder holds back, 1 blocks all of it and 0 lets all of it through (tinted by
+    /// `transmittance`). Overlapping occluders multiply what they let through.
+    #[serde(default = "opaque")]
+    pub opacity: f32,
+}
+
+fn white() -> Color {
+    Color::WHITE
+}
+
+fn opaque() -> f32 {
+    1.0
+}
+
+impl Default for LightOccluder {
+    fn default() -> Self {
+        Self {
+            shape: default(),
+            layers: ALL_LIGHT_LAYERS,
+            height: None,
+            transmittance: Color::WHITE,
+            opacity: 1.0,
+        }
+    }
+}
+
+impl LightOccluder {
+    pub fn rect(width: f32, height: f32) -> Self {
+        Self {
+            shape: OccluderShape::Rect { width, height },
+            ..default()
+        }
+    }
+
+    /// Linear color of the light that makes it through the occluder, black for opaque occluders
+    pub fn transmitted(&self) -> Vec3 {
+        let transmittance = Vec4::from(self.transmittance.as_linear_rgba_f32()).truncate();
+        (transmittance * (1.0 - self.opacity)).clamp(Vec3::ZERO, Vec3::ONE)
+    }
+
+    /// Everything but polylines encloses an area
+    pub fn is_closed(&self) -> bool {
+        !matches!(self.shape, OccluderShape::Polyline(_))
+    }
+
+    /// Corner points of the outline in local space, circles are approximated by [`CIRCLE_SEGMENTS`] points
+    pub fn outline(&self) -> Vec<Vec2> {
+        match &self.shape {
+            OccluderShape::Rect { width, height } => vec![
+                Vec2::ZERO,
+                Vec2::new(*width, 0.0),
+                Vec2::new(*width, -*height),
+                Vec2::new(0.0, -*height),
+            ],
+            OccluderShape::Polygon(points) | OccluderShape::Polyline(points) => points.clone(),
+            OccluderShape::Circle { radius } => (0..CIRCLE_SEGMENTS)
+                .map(|i| {
+                    let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                    Vec2::new(angle.cos(), angle.sin()) * *radius
+                })
+                .collect(),
+        }
+    }
+
+    /// Edges of the outline in world space, with the whole transform (including rotation and scale) applied
+    pub fn segments(&self, transform: &GlobalTransform) -> Vec<(Vec2, Vec2)> {
+        let points: Vec<Vec2> = self
+            .outline()
+            .into_iter()
+            .map(|point| transform.transform_point(point.extend(0.0)).truncate())
+            .collect();
+
+        let mut segments: Vec<(Vec2, Vec2)> = points.windows(2).map(|pair| (pair[0], pair[1])).collect();
+        if self.is_closed() && points.len() > 2 {
+            segments.push((points[points.len() - 1], points[0]));
+        }
+        segments
+    }
+
+    /// World space bounding box of the outline
+    pub fn bounds(&self, transform: &GlobalTransform) -> Rect {
+        let mut points = self
+            .outline()
+            .into_iter()
+            .map(|point| transform.transform_point(point.extend(0.0)).truncate());
+
+        let Some(first) = points.next() else {
+            let translation = transform.translation().truncate();
+            return Rect::from_corners(translation, translation);
+        };
+        points.fold(Rect::from_corners(first, first), |bounds, point| bounds.union_point(point))
+    }
+}
@@ -1,11 +1,109 @@
-use bevy::{prelude::*, render::{render_resource::{AsBindGroup, ShaderType}, extract_component::ExtractComponent}};
+use bevy::{prelude::*, render::{render_resource::AsBindGroup, extract_component::ExtractComponent}};
+use serde::{Deserialize, Serialize};
 
-#[derive(Component, Default, Clone, Copy, ExtractComponent, ShaderType, Debug)]
+use crate::lighting::Falloff;
+
+#[derive(Component, Clone, Copy, ExtractComponent, Debug, Serialize, Deserialize)]
 pub struct LightSource {
+    /// Follows the translation of the light's `Transform`, which is what gets saved
+    #[serde(skip)]
     pub position: Vec2,
     pub color: Vec4,
     pub intensity: f32,
     pub radius: f32,
-    pub is_active: u32
+    pub is_active: u32,
+    /// Size of the light itself, bigger lights cast wider penumbrae. 0 gives hard shadows
+    #[serde(default)]
+    pub source_radius: f32,
+    #[serde(default)]
+    pub falloff: Falloff,
+    /// Turns the light into a spotlight shining along the x axis of its `Transform`
+    #[serde(default)]
+    pub spot: Option<Spot>,
+    #[serde(default)]
+    pub shape: LightShape,
+    /// How far above the map the light hangs. Low lights graze the bumps of the normal map, high lights hit them
+    /// head on and shine over occluders lower than them, see [`super::LightOccluder::height`].
+    #[serde(default = "default_light_height")]
+    pub height: f32,
+    /// Static lights get baked into the lightmap and aren't computed at runtime while it is up to date,
+    /// see `BakedLighting`
+    #[serde(default)]
+    pub is_static: bool,
+    /// Bitmask of light layers, the light is only blocked by occluders sharing a layer with it. A light without
+    /// any layers shines through every wall.
+    #[serde(default = "all_light_layers")]
+    pub layers: u32,
+}
+
+impl Default for LightSource {
+    fn default() -> Self {
+        Self {
+            position: Vec2::ZERO,
+            color: Vec4::ZERO,
+            intensity: 0.0,
+            radius: 0.0,
+            is_active: 0,
+            source_radius: 0.0,
+            falloff: default(),
+            spot: None,
+            shape: default(),
+            height: DEFAULT_LIGHT_HEIGHT,
+            is_static: false,
+            layers: ALL_LIGHT_LAYERS,
+        }
+    }
+}
+
+pub const DEFAULT_LIGHT_HEIGHT: f32 = 50.0;
+
+/// Lights and occluders are on every layer unless told otherwise
+pub const ALL_LIGHT_LAYERS: u32 = u32::MAX;
+
+fn default_light_height() -> f32 {
+    DEFAULT_LIGHT_HEIGHT
+}
+
+pub(crate) fn all_light_layers() -> u32 {
+    ALL_LIGHT_LAYERS
+}
+
+/// What the light is emitted from, in the local space of the light's `Transform`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum LightShape {
+    #[default]
+    Point,
+    /// A line along the x axis centered on the light, like a neon tube
+    Line { length: f32 },
+    /// A rectangle centered on the light, like a window
+    Rect { width: f32, height: f32 },
+}
+
+impl LightShape {
+    /// Half extents along the local x and y axis, zero for point lights
+    pub fn half_extents(&self) -> Vec2 {
+        match *self {
+            LightShape::Point => Vec2::ZERO,
+            LightShape::Line { length } => Vec2::new(length / 2.0, 0.0),
+            LightShape::Rect { width, height } => Vec2::new(width, height) / 2.0,
+        }
+    }
+}
+
+/// Cone of a spotlight, both angles are measured from the direction to the edge of the cone in radians.
+/// The light fades out between the inner and the outer angle.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Spot {
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+}
+
+impl Default for Spot {
+    fn default() -> Self {
+        Self {
+            inner_angle: 20f32.to_radians(),
+            outer_angle: 35f32.to_radians(),
+        }
+    }
 }
 
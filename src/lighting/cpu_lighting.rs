@@ -0,0 +1,499 @@
+//! CPU version of `fragment()` in `assets/shaders/lighting_pass.wgsl`.
+//!
+//! Everything in here mirrors the shader line by line, so it can be used to check the GPU output
+//! without a GPU (golden images) or as a software fallback. If you change the shader, change this too.
+
+use std::{error::Error, f32::consts::TAU, fmt, path::Path};
+
+use bevy::{
+    prelude::*,
+    render::{
+        color::SrgbColorSpace,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+
+use super::{
+    DistanceField, FalloffCurves, GlobalIllumination, GpuLightSource, GpuSegment, LightOccluder, LightSource,
+    LightingGlobals, OccluderGrid, ShadowMode, FALLOFF_CURVE_SAMPLES,
+};
+
+/// Same as `crossing` in the shader: where the segment a-b crosses the segment c-d, from 0 at a to 1 at b
+pub fn crossing(a: Vec2, b: Vec2, c: Vec2, d: Vec2) -> Option<f32> {
+    let denominator = (d.y - c.y) * (b.x - a.x) - (d.x - c.x) * (b.y - a.y);
+    let u_a = ((d.x - c.x) * (a.y - c.y) - (d.y - c.y) * (a.x - c.x)) / denominator;
+    let u_b = ((b.x - a.x) * (a.y - c.y) - (b.y - a.y) * (a.x - c.x)) / denominator;
+
+    ((0.0..=1.0).contains(&u_a) && (0.0..=1.0).contains(&u_b)).then_some(u_a)
+}
+
+/// Same as `blocking_crossing` in the shader: where the segment blocks the ray from `start` on the map to `end` on a
+/// light with these `layers`, hanging `height` above the map, from 0 at `start` to 1 at `end`
+pub fn blocking_crossing(segment: &GpuSegment, start: Vec2, end: Vec2, layers: u32, height: f32) -> Option<f32> {
+    if segment.layers & layers == 0 {
+        return None;
+    }
+    crossing(start, end, segment.a, segment.b).filter(|t| segment.height > height * t)
+}
+
+/// Same as `is_first_crossing` in the shader: whether the ray crosses no other edge of the occluder of
+/// `segments[index]` before it crosses this one at `t`. Every occluder tints the light once, no matter how many of its
+/// edges the ray crosses, so rays starting or ending inside of it get tinted as much as rays through it.
+pub fn is_first_crossing(
+    segments: &[GpuSegment],
+    index: usize,
+    t: f32,
+    start: Vec2,
+    end: Vec2,
+    layers: u32,
+    height: f32,
+) -> bool {
+    let first = segments[index].first;
+    segments
+        .iter()
+        .enumerate()
+        .skip(first as usize)
+        .take_while(|(_, other)| other.first == first)
+        .filter(|(other_index, _)| *other_index != index)
+        .all(|(other_index, other)| match blocking_crossing(other, start, end, layers, height) {
+            Some(other_t) => other_t > t || (other_t == t && other_index > index),
+            None => true,
+        })
+}
+
+/// Same as `hash` in the shader (PCG)
+pub fn hash(value: u32) -> u32 {
+    let state = value.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// Same as `pixel_noise` in the shader
+pub fn pixel_noise(p: Vec2) -> f32 {
+    let cell = p.floor();
+    hash((cell.x as i32 as u32) ^ hash(cell.y as i32 as u32)) as f32 / u32::MAX as f32
+}
+
+/// What shadow rays are tested against, see [`ShadowMode`]
+#[derive(Clone, Debug, Default)]
+pub struct Occlusion {
+    pub grid: OccluderGrid,
+    /// Marched through before the grid if there is one, like with `DISTANCE_FIELD` in the shader
+    pub distance_field: Option<DistanceField>,
+}
+
+/// Same as `transmittance` in the shader: how much of the light gets from `end` to `start` through the occluders,
+/// one for every color channel. The distance field treats every occluder as opaque, rays that run out of steps in
+/// it are tested against the segments from where they stopped.
+pub fn transmittance(start: Vec2, end: Vec2, layers: u32, height: f32, occlusion: &Occlusion) -> Vec3 {
+    let Some(distance_field) = &occlusion.distance_field else {
+        return segments_transmittance(start, end, layers, height, &occlusion.grid);
+    };
+    match distance_field.march(start, end) {
+        None => Vec3::ZERO,
+        Some(marched) if marched >= 1.0 => Vec3::ONE,
+        Some(marched) => segments_transmittance(start.lerp(end, marched), end, layers, height, &occlusion.grid),
+    }
+}
+
+/// Same as `segments_transmittance` in the shader: only the segments in the grid cells along the ray are tested, see
+/// [`blocking_crossing`] for `layers` and `height`
+pub fn segments_transmittance(start: Vec2, end: Vec2, layers: u32, height: f32, grid: &OccluderGrid) -> Vec3 {
+    let mut transmitted = Vec3::ONE;
+    grid.visit_cells(start, end, |cell, (t_enter, t_exit)| {
+        for index in grid.cell_segments(cell) {
+            let segment = &grid.segments[*index as usize];
+            // A segment can be in several cells, it only counts in the one the ray crosses it in
+            match blocking_crossing(segment, start, end, layers, height) {
+                Some(t)
+                    if t >= t_enter
+                        && t < t_exit
+                        && is_first_crossing(&grid.segments, *index as usize, t, start, end, layers, height) =>
+                {
+                    transmitted *= segment.unpack_transmittance()
+                }
+                _ => {}
+            }
+        }
+        transmitted.max_element() <= 0.0
+    });
+    transmitted
+}
+
+/// Same as `SHADOW_SAMPLES` in the shader
+pub const SHADOW_SAMPLES: u32 = 8;
+
+/// Same as `AREA_SAMPLES` in the shader
+pub const AREA_SAMPLES: u32 = 8;
+
+/// Same as `light_visibility` in the shader
+pub fn light_visibility(position: Vec2, light: &GpuLightSource, occlusion: &Occlusion) -> Vec3 {
+    if light.source_radius <= 0.0 {
+        return transmittance(position, light.position, light.layers, light.height, occlusion);
+    }
+
+    let to_light = light.position - position;
+    let across = Vec2::new(-to_light.y, to_light.x) / to_light.length().max(0.0001);
+    let jitter = pixel_noise(position);
+
+    let visible: Vec3 = (0..SHADOW_SAMPLES)
+        .map(|i| {
+            let t = (i as f32 + jitter) / SHADOW_SAMPLES as f32 * 2.0 - 1.0;
+            let end = light.position + across * light.source_radius * t;
+            transmittance(position, end, light.layers, light.height, occlusion)
+        })
+        .sum();
+    visible / SHADOW_SAMPLES as f32
+}
+
+/// Same as `sample_falloff_curve` in the shader, `curves` is [`FalloffCurves::resampled`]
+pub fn sample_falloff_curve(curves: &[f32], row: u32, t: f32) -> f32 {
+    let rows = (curves.len() / FALLOFF_CURVE_SAMPLES).max(1);
+    let last = FALLOFF_CURVE_SAMPLES - 1;
+    let y = (row as usize).min(rows - 1);
+    let x = t.clamp(0.0, 1.0) * last as f32;
+    let x0 = x.floor() as usize;
+    let x1 = (x0 + 1).min(last);
+    let texel = |x: usize| curves.get(y * FALLOFF_CURVE_SAMPLES + x).copied().unwrap_or(0.0);
+    texel(x0) + (texel(x1) - texel(x0)) * (x - x.floor())
+}
+
+/// Same as `falloff` in the shader
+pub fn falloff(light: &GpuLightSource, t: f32, curves: &[f32]) -> f32 {
+    match light.falloff {
+        1 => 1.0 - t,
+        2 => 1.0 - t * t * (3.0 - 2.0 * t),
+        3 => sample_falloff_curve(curves, light.curve_index, t),
+        _ => {
+            let window = (1.0 - t * t * t * t).clamp(0.0, 1.0);
+            window * window / (1.0 + 25.0 * t * t)
+        }
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Same as `spot_factor` in the shader
+pub fn spot_factor(light: &GpuLightSource, origin: Vec2, position: Vec2) -> f32 {
+    if light.cos_outer < -1.0 {
+        return 1.0;
+    }
+    let to_pixel = position - origin;
+    let cos_angle = light.direction.dot(to_pixel / to_pixel.length().max(0.0001));
+    smoothstep(light.cos_outer, light.cos_inner, cos_angle)
+}
+
+/// Same as `attenuation` in the shader
+pub fn attenuation(light: &GpuLightSource, origin: Vec2, position: Vec2, curves: &[f32]) -> f32 {
+    let light_distance = (origin - position).length();
+    if light_distance >= light.radius {
+        return 0.0;
+    }
+    falloff(light, light_distance / light.radius, curves) * spot_factor(light, origin, position)
+}
+
+/// Same as `light_contribution` in the shader, tinted by the translucent occluders in the way
+pub fn light_contribution(light: &GpuLightSource, position: Vec2, occlusion: &Occlusion, curves: &[f32]) -> Vec3 {
+    if light.extent_a == Vec2::ZERO && light.extent_b == Vec2::ZERO {
+        let point_attenuation = attenuation(light, light.position, position, curves);
+        if point_attenuation <= 0.0 {
+            return Vec3::ZERO;
+        }
+        return point_attenuation * light_visibility(position, light, occlusion);
+    }
+
+    let jitter = pixel_noise(position);
+    let sum: Vec3 = (0..AREA_SAMPLES)
+        .map(|i| {
+            let u = (i as f32 + jitter) / AREA_SAMPLES as f32 * 2.0 - 1.0;
+            let v = fract(jitter + i as f32 * 0.618034) * 2.0 - 1.0;
+            let origin = light.position + light.extent_a * u + light.extent_b * v;
+            let sample_attenuation = attenuation(light, origin, position, curves);
+            if sample_attenuation > 0.0 {
+                sample_attenuation * transmittance(position, origin, light.layers, light.height, occlusion)
+            } else {
+                Vec3::ZERO
+            }
+        })
+        .sum();
+    sum / AREA_SAMPLES as f32
+}
+
+// WGSL's fract, which unlike `f32::fract` is never negative
+fn fract(x: f32) -> f32 {
+    x - x.floor()
+}
+
+/// Same as `normal_shading` in the shader
+pub fn normal_shading(light: &GpuLightSource, position: Vec2, normal: Vec3) -> f32 {
+    let to_light = (light.position - position).extend(light.height);
+    normal.dot(to_light.normalize()).max(0.0)
+}
+
+/// Same as `EMISSIVE_SPREAD_SAMPLES` in the shader
+pub const EMISSIVE_SPREAD_SAMPLES: u32 = 12;
+
+/// Same as `emissive_spill` in the shader, `emissive_map` is expected to be an 8 bit RGBA image
+pub fn emissive_spill(emissive_map: &Image, uv: Vec2, position: Vec2, globals: &LightingGlobals) -> Vec3 {
+    if globals.emissive_spread <= 0.0 {
+        return Vec3::ZERO;
+    }
+
+    let srgb = is_srgb(emissive_map);
+    let jitter = pixel_noise(position) * TAU;
+    let (sum, weight_sum) = (0..EMISSIVE_SPREAD_SAMPLES).fold((Vec3::ZERO, 0.0), |(sum, weight_sum), i| {
+        let ring = if i % 2 == 0 { 0.5 } else { 1.0 };
+        let angle = jitter + i as f32 * TAU / EMISSIVE_SPREAD_SAMPLES as f32;
+        let offset = Vec2::new(angle.cos(), angle.sin()) * ring * globals.emissive_spread;
+        let weight = 1.5 - ring;
+        let emitted = sample_bilinear(emissive_map, uv + offset / globals.map_size, srgb).truncate();
+        (sum + emitted * weight, weight_sum + weight)
+    });
+    sum / weight_sum * globals.emissive_light
+}
+
+/// Everything the fragment shader samples from its textures for one pixel, all linear
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PixelSample {
+    pub color: Vec4,
+    /// From the normal map, if there is one
+    pub normal: Option<Vec3>,
+    /// From the emissive map, zero without one
+    pub emissive: Vec3,
+    /// See [`emissive_spill`], zero without an emissive map
+    pub emissive_spill: Vec3,
+    /// From the baked lightmap, zero without one. See [`super::bake_lightmap`].
+    pub baked_light: Vec3,
+}
+
+/// The light loop of the fragment shader, the light all active lights add to a pixel
+pub fn direct_light(
+    normal: Option<Vec3>,
+    world_position: Vec2,
+    lights: &[GpuLightSource],
+    occlusion: &Occlusion,
+    curves: &[f32],
+) -> Vec3 {
+    let mut light_sum = Vec3::ZERO;
+
+    for light in lights.iter().filter(|light| light.is_active != 0) {
+        let reach = light.radius + light.extent_a.length() + light.extent_b.length();
+        if (light.position - world_position).length() >= reach {
+            continue;
+        }
+
+        let shading = normal.map_or(1.0, |normal| normal_shading(light, world_position, normal));
+        light_sum += light.color.truncate()
+            * light.intensity
+            * shading
+            * light_contribution(light, world_position, occlusion, curves);
+    }
+
+    light_sum
+}
+
+/// Same as `accumulate` in the shader, the light falling onto a pixel
+pub fn accumulated_light(
+    sample: &PixelSample,
+    world_position: Vec2,
+    lights: &[GpuLightSource],
+    occlusion: &Occlusion,
+    curves: &[f32],
+    globals: &LightingGlobals,
+) -> Vec3 {
+    let mut light_sum = globals.ambient + sample.baked_light;
+    light_sum += direct_light(sample.normal, world_position, lights, occlusion, curves);
+    light_sum + sample.emissive_spill
+}
+
+/// Both halves of the lighting pass for a single pixel, with the light texture at full resolution
+pub fn shade_pixel(
+    sample: &PixelSample,
+    world_position: Vec2,
+    lights: &[GpuLightSource],
+    occlusion: &Occlusion,
+    curves: &[f32],
+    globals: &LightingGlobals,
+) -> Vec4 {
+    let light_sum = accumulated_light(sample, world_position, lights, occlusion, curves, globals);
+
+    let color_rgb = sample.color.truncate();
+    (color_rgb.lerp(color_rgb * light_sum, globals.darkness) + sample.emissive).extend(sample.color.w)
+}
+
+/// Renders what the lighting pass outputs for the map `source`. All images have to be 8 bit RGBA like the ones
+/// loaded from png, anything else is an [`UnsupportedFormat`]. Just like in `setup_map` the map is centered on the
+/// world origin and every pixel of the image covers one world unit.
+///
+/// Lights and occluders are paired with their world space `Transform`. The normal and emissive maps are sampled
+/// with the same uv as `source`, so they may have a different resolution.
+///
+/// The result is the HDR view before exposure, tonemapping and bloom from [`super::HdrSettings`], which the camera
+/// applies afterwards, so it is clamped to 8 bits wherever the lights add up to more than white.
+pub fn render_lightmap(
+    source: &Image,
+    normal_map: Option<&Image>,
+    emissive_map: Option<&Image>,
+    lights: &[(LightSource, Transform)],
+    occluders: &[(LightOccluder, Transform)],
+    falloff_curves: &FalloffCurves,
+    illumination: &GlobalIllumination,
+) -> Result<Image, UnsupportedFormat> {
+    let srgb = check_format(source)?;
+    for image in normal_map.into_iter().chain(emissive_map) {
+        check_format(image)?;
+    }
+
+    let lights = gpu_lights(lights);
+    let occlusion = occlusion(occluders, illumination.shadows);
+    let grid = &occlusion.grid;
+    let curves = falloff_curves.resampled();
+    let size = source.texture_descriptor.size;
+    let map_size = Vec2::new(size.width as f32, size.height as f32);
+    let globals = LightingGlobals {
+        light_count: lights.len() as u32,
+        segment_count: grid.segments.len() as u32,
+        ambient: illumination.ambient(),
+        darkness: illumination.darkness,
+        emissive_spread: illumination.emissive_spread,
+        emissive_light: illumination.emissive_light,
+        grid_origin: grid.origin,
+        grid_cell_size: grid.cell_size,
+        grid_size: grid.size,
+        field_origin: occlusion.distance_field.as_ref().map_or(Vec2::ZERO, |field| field.origin),
+        field_texel_size: occlusion.distance_field.as_ref().map_or(0.0, |field| field.texel_size),
+        map_size,
+    };
+
+    let half_size = map_size / 2.0;
+    let mut data = Vec::with_capacity(source.data.len());
+
+    for (index, pixel) in source.data.chunks_exact(4).enumerate() {
+        let x = (index as u32 % size.width) as f32;
+        let y = (index as u32 / size.width) as f32;
+        // Pixel centers, with y pointing up like in the world
+        let world_position = Vec2::new(x + 0.5 - half_size.x, half_size.y - y - 0.5);
+        let uv = Vec2::new(x + 0.5, y + 0.5) / map_size;
+
+        let sample = PixelSample {
+            color: decode(pixel, srgb),
+            normal: normal_map.map(|normal_map| sample_normal(normal_map, uv)),
+            emissive: emissive_map
+                .map_or(Vec3::ZERO, |emissive_map| sample_bilinear(emissive_map, uv, is_srgb(emissive_map)).truncate()),
+            emissive_spill: emissive_map
+                .map_or(Vec3::ZERO, |emissive_map| emissive_spill(emissive_map, uv, world_position, &globals)),
+            baked_light: Vec3::ZERO,
+        };
+        let shaded = shade_pixel(&sample, world_position, &lights, &occlusion, &curves, &globals);
+        data.extend_from_slice(&encode(shaded, srgb));
+    }
+
+    Ok(Image::new(
+        Extent3d { width: size.width, height: size.height, ..default() },
+        TextureDimension::D2,
+        data,
+        source.texture_descriptor.format,
+    ))
+}
+
+/// Reads the image at `path` in the asset directory `assets` as 8 bit RGBA, which is what the CPU lighting reads.
+/// Paths are resolved the way the asset server does it, absolute paths are used as they are.
+pub fn read_asset_image(assets: &Path, path: &Path, srgb: bool) -> Result<Image, Box<dyn Error>> {
+    let image = image::open(assets.join(path))?.into_rgba8();
+    let (width, height) = image.dimensions();
+    let format = if srgb { TextureFormat::Rgba8UnormSrgb } else { TextureFormat::Rgba8Unorm };
+    Ok(Image::new(Extent3d { width, height, ..default() }, TextureDimension::D2, image.into_raw(), format))
+}
+
+/// The CPU lighting only reads 8 bit RGBA images, this is the format of an image it was given instead
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnsupportedFormat(pub TextureFormat);
+
+impl fmt::Display for UnsupportedFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "only 8 bit RGBA images are supported, got {:?}", self.0)
+    }
+}
+
+impl Error for UnsupportedFormat {}
+
+/// Whether the texels of `image` are sRGB encoded, if it has a format the CPU lighting can read
+pub(crate) fn check_format(image: &Image) -> Result<bool, UnsupportedFormat> {
+    match image.texture_descriptor.format {
+        TextureFormat::Rgba8UnormSrgb => Ok(true),
+        TextureFormat::Rgba8Unorm => Ok(false),
+        format => Err(UnsupportedFormat(format)),
+    }
+}
+
+pub(crate) fn gpu_lights(lights: &[(LightSource, Transform)]) -> Vec<GpuLightSource> {
+    lights.iter().map(|(light, transform)| GpuLightSource::new(light, &GlobalTransform::from(*transform))).collect()
+}
+
+// There are no entities outside of the world, so every occluder gets a made up one as the owner of its segments
+pub(crate) fn occlusion(occluders: &[(LightOccluder, Transform)], shadows: ShadowMode) -> Occlusion {
+    let occluders: Vec<(&LightOccluder, GlobalTransform)> =
+        occluders.iter().map(|(occluder, transform)| (occluder, GlobalTransform::from(*transform))).collect();
+    let grid = OccluderGrid::new(occluders.iter().enumerate().flat_map(|(index, (occluder, transform))| {
+        GpuSegment::from_occluder(occluder, transform).map(move |segment| (Entity::from_raw(index as u32), segment))
+    }));
+    let distance_field = match shadows {
+        ShadowMode::DistanceField { texel_size } => {
+            DistanceField::new(occluders.iter().map(|(occluder, transform)| (*occluder, transform)), texel_size)
+        }
+        ShadowMode::Segments => None,
+    };
+    Occlusion { grid, distance_field }
+}
+
+// Normal maps are read as they are, see `setup_map`
+pub(crate) fn sample_normal(normal_map: &Image, uv: Vec2) -> Vec3 {
+    (sample_bilinear(normal_map, uv, false).truncate() * 2.0 - 1.0).normalize()
+}
+
+// Everything but sRGB is read as linear, `check_format` rejects what can't be read at all
+fn is_srgb(image: &Image) -> bool {
+    image.texture_descriptor.format == TextureFormat::Rgba8UnormSrgb
+}
+
+// Linear filtering with clamp to edge addressing, like bevy's default sampler
+fn sample_bilinear(image: &Image, uv: Vec2, srgb: bool) -> Vec4 {
+    let size = image.texture_descriptor.size;
+    let texel = |x: i32, y: i32| {
+        let x = x.clamp(0, size.width as i32 - 1) as usize;
+        let y = y.clamp(0, size.height as i32 - 1) as usize;
+        let index = (y * size.width as usize + x) * 4;
+        decode(&image.data[index..index + 4], srgb)
+    };
+
+    let position = uv * Vec2::new(size.width as f32, size.height as f32) - 0.5;
+    let (x, y) = (position.x.floor(), position.y.floor());
+    let (tx, ty) = (position.x - x, position.y - y);
+    let (x, y) = (x as i32, y as i32);
+    let top = texel(x, y).lerp(texel(x + 1, y), tx);
+    let bottom = texel(x, y + 1).lerp(texel(x + 1, y + 1), tx);
+    top.lerp(bottom, ty)
+}
+
+fn decode(pixel: &[u8], srgb: bool) -> Vec4 {
+    let channel = |value: u8| {
+        let value = value as f32 / u8::MAX as f32;
+        if srgb {
+            value.nonlinear_to_linear_srgb()
+        } else {
+            value
+        }
+    };
+
+    Vec4::new(channel(pixel[0]), channel(pixel[1]), channel(pixel[2]), pixel[3] as f32 / u8::MAX as f32)
+}
+
+fn encode(color: Vec4, srgb: bool) -> [u8; 4] {
+    let to_u8 = |value: f32| (value.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8;
+    let channel = |value: f32| to_u8(if srgb { value.linear_to_nonlinear_srgb() } else { value });
+
+    [channel(color.x), channel(color.y), channel(color.z), to_u8(color.w)]
+}
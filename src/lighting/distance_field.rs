@@ -0,0 +1,411 @@
+use std::sync::{
+    mpsc::{self, Receiver, TryRecvError},
+    Mutex,
+};
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+    tasks::AsyncComputeTaskPool,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{GlobalIllumination, LightOccluder, LightSource, OccluderGrid, ALL_LIGHT_LAYERS};
+
+/// The distance field never gets bigger than this along either axis, bigger levels get coarser texels instead
+pub const MAX_DISTANCE_FIELD_SIZE: u32 = 1024;
+/// Free texels around the occluders, so the distance outside of the field can be measured to its border
+const FIELD_MARGIN: u32 = 2;
+/// Same as `MAX_MARCH_STEPS` in the shader
+pub const MAX_MARCH_STEPS: u32 = 128;
+/// Seconds without occluder changes before the field gets rebuilt, so dragging a wall doesn't rebuild it every frame
+pub const REBUILD_DELAY: f32 = 0.25;
+
+/// What shadow rays are tested against
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ShadowMode {
+    /// Exact tests against the occluder edges along the ray, see [`OccluderGrid`]
+    #[default]
+    Segments,
+    /// Raymarching through a [`DistanceField`] of the occluders with `texel_size` world units per texel. The cost
+    /// depends on the size of the texture and the distance to the occluders, not on how many occluders there are.
+    /// The field can't tell occluders apart, so every occluder blocks every light fully, no matter its layers, height
+    /// and opacity. The editor only switches to it while the level is [supported](DistanceField::supports), and
+    /// hides the inspector fields that would break that. Only occluder shapes are rasterized, sprites have to be
+    /// traced into walls first, see [`super::trace_occluders`].
+    DistanceField { texel_size: f32 },
+}
+
+/// The distance field texture while [`ShadowMode::DistanceField`] is on, kept up to date by `update_distance_field`
+#[derive(Resource, ExtractResource, Clone, Default)]
+pub struct OccluderDistanceField {
+    pub texture: Option<Handle<Image>>,
+    /// See [`DistanceField::origin`]
+    pub origin: Vec2,
+    pub texel_size: f32,
+}
+
+/// Signed distance to the nearest occluder for every texel of a grid laid over the occluders
+#[derive(Clone, Debug, Default)]
+pub struct DistanceField {
+    /// World space corner of texel (0, 0), texels go along +x and +y from here
+    pub origin: Vec2,
+    /// World units per texel
+    pub texel_size: f32,
+    pub size: UVec2,
+    /// In world units and negative inside occluders, row by row starting at `origin`
+    pub distances: Vec<f32>,
+}
+
+impl DistanceField {
+    /// Rasterizes the occluders and turns them into a distance field with jump flooding. Closed occluders are filled,
+    /// every edge is drawn as a line without diagonal gaps. There is no field without occluders.
+    pub fn new<'a>(
+        occluders: impl IntoIterator<Item = (&'a LightOccluder, &'a GlobalTransform)>,
+        texel_size: f32,
+    ) -> Option<Self> {
+        let occluders: Vec<(bool, Vec<(Vec2, Vec2)>)> = occluders
+            .into_iter()
+            .map(|(occluder, transform)| (occluder.is_closed(), occluder.segments(transform)))
+            .filter(|(_, segments)| !segments.is_empty())
+            .collect();
+        let bounds = occluders.iter().flat_map(|(_, segments)| segments.iter().flat_map(|(a, b)| [*a, *b])).fold(
+            None,
+            |bounds: Option<Rect>, point| {
+                Some(bounds.map_or(Rect::from_corners(point, point), |bounds| bounds.union_point(point)))
+            },
+        )?;
+
+        let max_texels = (MAX_DISTANCE_FIELD_SIZE - 2 * FIELD_MARGIN) as f32;
+        let texel_size = texel_size.max(bounds.size().max_element() / max_texels).max(0.01);
+        let mut field = Self {
+            origin: bounds.min - FIELD_MARGIN as f32 * texel_size,
+            texel_size,
+            size: (bounds.size() / texel_size).ceil().as_uvec2() + 1 + 2 * FIELD_MARGIN,
+            distances: Vec::new(),
+        };
+
+        let mut occupied = vec![false; (field.size.x * field.size.y) as usize];
+        for (is_closed, segments) in occluders.iter() {
+            if *is_closed {
+                field.fill(&mut occupied, segments);
+            }
+            for (a, b) in segments.iter() {
+                field.draw_line(&mut occupied, *a, *b);
+            }
+        }
+
+        // Distance to the nearest occupied texel outside and to the nearest free texel inside, the border sits halfway
+        let outside = jump_flood(field.size, &occupied, true);
+        let inside = jump_flood(field.size, &occupied, false);
+        field.distances = occupied
+            .iter()
+            .zip(outside.iter().zip(inside.iter()))
+            .map(|(is_occupied, (outside, inside))| match is_occupied {
+                true => -(inside - 0.5) * texel_size,
+                false => (outside - 0.5) * texel_size,
+            })
+            .collect();
+        Some(field)
+    }
+
+    /// Whether the field casts the same shadows as [`ShadowMode::Segments`] would. Every occluder has to be opaque,
+    /// on every layer and block lights at any height, and no light can be on no layer at all (those pass every
+    /// occluder).
+    pub fn supports<'a>(
+        occluders: impl IntoIterator<Item = &'a LightOccluder>,
+        lights: impl IntoIterator<Item = &'a LightSource>,
+    ) -> bool {
+        occluders.into_iter().all(|occluder| {
+            occluder.layers == ALL_LIGHT_LAYERS && occluder.height.is_none() && occluder.transmitted() == Vec3::ZERO
+        }) && lights.into_iter().all(|light| light.layers != 0)
+    }
+
+    fn texel(&self, point: Vec2) -> IVec2 {
+        ((point - self.origin) / self.texel_size).floor().as_ivec2()
+    }
+
+    fn mark(&self, occupied: &mut [bool], texel: IVec2) {
+        if texel.cmpge(IVec2::ZERO).all() && texel.cmplt(self.size.as_ivec2()).all() {
+            occupied[(texel.y as u32 * self.size.x + texel.x as u32) as usize] = true;
+        }
+    }
+
+    // Even-odd scanline fill, a texel is inside if its center is
+    fn fill(&self, occupied: &mut [bool], segments: &[(Vec2, Vec2)]) {
+        let (min_y, max_y) = segments
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min_y, max_y), (a, b)| (min_y.min(a.y).min(b.y), max_y.max(a.y).max(b.y)));
+        let first_row = self.texel(Vec2::new(0.0, min_y)).y.max(0);
+        let last_row = self.texel(Vec2::new(0.0, max_y)).y.min(self.size.y as i32 - 1);
+
+        for row in first_row..=last_row {
+            let y = self.origin.y + (row as f32 + 0.5) * self.texel_size;
+            let mut crossings: Vec<f32> = segments
+                .iter()
+                .filter(|(a, b)| (a.y > y) != (b.y > y))
+                .map(|(a, b)| a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x))
+                .collect();
+            crossings.sort_by(f32::total_cmp);
+
+            for span in crossings.chunks_exact(2) {
+                let first = ((span[0] - self.origin.x) / self.texel_size - 0.5).ceil() as i32;
+                let last = ((span[1] - self.origin.x) / self.texel_size - 0.5).floor() as i32;
+                for column in first..=last {
+                    self.mark(occupied, IVec2::new(column, row));
+                }
+            }
+        }
+    }
+
+    // Steps along the line in quarter texels. Diagonal steps get an extra texel, so rays can't slip through the
+    // corners.
+    fn draw_line(&self, occupied: &mut [bool], a: Vec2, b: Vec2) {
+        let steps = ((b - a).length() / self.texel_size * 4.0).ceil().max(1.0) as u32;
+        let mut previous = self.texel(a);
+        self.mark(occupied, previous);
+        for step in 1..=steps {
+            let texel = self.texel(a.lerp(b, step as f32 / steps as f32));
+            if texel.x != previous.x && texel.y != previous.y {
+                self.mark(occupied, IVec2::new(previous.x, texel.y));
+            }
+            self.mark(occupied, texel);
+            previous = texel;
+        }
+    }
+
+    /// Same as `field_distance` in the shader. Outside of the field this is the distance to its border, which is
+    /// never more than the distance to the nearest occluder.
+    pub fn distance(&self, point: Vec2) -> f32 {
+        let texel = (point - self.origin) / self.texel_size;
+        let size = self.size.as_vec2();
+        let outside = (-texel).max(texel - size).max(Vec2::ZERO);
+        if outside.cmpgt(Vec2::ZERO).any() {
+            return outside.length() * self.texel_size;
+        }
+        let texel = texel.as_ivec2().min(self.size.as_ivec2() - 1);
+        self.distances[(texel.y as u32 * self.size.x + texel.x as u32) as usize]
+    }
+
+    /// Same as `march` in the shader with `DISTANCE_FIELD`: sphere tracing from `start` to `end`. Returns how far the
+    /// ray got, from 0 at `start` to 1 at `end`, or `None` if it hit an occluder. Rays crawling along an occluder run
+    /// out of steps before they get to `end`, the rest of them has to be tested against the segments.
+    pub fn march(&self, start: Vec2, end: Vec2) -> Option<f32> {
+        let to_end = end - start;
+        let ray_length = to_end.length();
+        let direction = to_end / ray_length.max(0.0001);
+        // The nearest texel can be off by half a texel diagonal
+        let slack = 0.7072 * self.texel_size;
+
+        let mut t = 0.0;
+        for _ in 0..MAX_MARCH_STEPS {
+            let distance = self.distance(start + direction * t);
+            if distance <= 0.0 {
+                return None;
+            }
+            t += (distance - slack).max(0.5 * self.texel_size);
+            if t >= ray_length {
+                return Some(1.0);
+            }
+        }
+        Some(t / ray_length)
+    }
+
+    /// One float per texel, for `textureLoad` in the shader
+    pub fn to_image(&self) -> Image {
+        Image::new(
+            Extent3d { width: self.size.x, height: self.size.y, ..default() },
+            TextureDimension::D2,
+            self.distances.iter().flat_map(|distance| distance.to_le_bytes()).collect(),
+            TextureFormat::R32Float,
+        )
+    }
+}
+
+// Jump flooding: every texel keeps the nearest seed it has heard of and asks its neighbors at halving distances about
+// theirs, with one more pass at distance 1 to fix most of the errors. Seeds are the texels where `occupied` equals
+// `seed`. Returns the distance to the nearest seed in texels.
+fn jump_flood(size: UVec2, occupied: &[bool], seed: bool) -> Vec<f32> {
+    let index = |texel: IVec2| (texel.y as u32 * size.x + texel.x as u32) as usize;
+    let mut nearest: Vec<Option<IVec2>> = (0..size.x * size.y)
+        .map(|i| (occupied[i as usize] == seed).then(|| IVec2::new((i % size.x) as i32, (i / size.x) as i32)))
+        .collect();
+
+    let mut step = size.max_element().next_power_of_two() / 2;
+    let mut steps = Vec::new();
+    while step >= 1 {
+        steps.push(step as i32);
+        step /= 2;
+    }
+    steps.push(1);
+
+    for step in steps {
+        let previous = nearest.clone();
+        for y in 0..size.y as i32 {
+            for x in 0..size.x as i32 {
+                let texel = IVec2::new(x, y);
+                let mut best = previous[index(texel)];
+                for offset_y in [-step, 0, step] {
+                    for offset_x in [-step, 0, step] {
+                        let neighbor = texel + IVec2::new(offset_x, offset_y);
+                        if neighbor.cmplt(IVec2::ZERO).any() || neighbor.cmpge(size.as_ivec2()).any() {
+                            continue;
+                        }
+                        let Some(candidate) = previous[index(neighbor)] else {
+                            continue;
+                        };
+                        let closer = best.map_or(true, |best| {
+                            (candidate - texel).as_vec2().length_squared() < (best - texel).as_vec2().length_squared()
+                        });
+                        if closer {
+                            best = Some(candidate);
+                        }
+                    }
+                }
+                nearest[index(texel)] = best;
+            }
+        }
+    }
+
+    nearest
+        .iter()
+        .enumerate()
+        .map(|(i, nearest)| {
+            let texel = IVec2::new((i as u32 % size.x) as i32, (i as u32 / size.x) as i32);
+            nearest.map_or(f32::MAX, |nearest| (nearest - texel).as_vec2().length())
+        })
+        .collect()
+}
+
+// What `update_distance_field` remembers between frames
+#[derive(Default)]
+pub(crate) struct FieldRebuild {
+    mode: Option<ShadowMode>,
+    // When the occluders last changed, until the field gets rebuilt
+    last_change: Option<f32>,
+    // The rebuild running on the async compute pool, in a mutex because `Local` needs it to be `Sync`
+    pending: Option<Mutex<Receiver<Option<DistanceField>>>>,
+}
+
+// Starts rebuilding the distance field right away when the shadow mode changes, and `REBUILD_DELAY` after the last
+// change to the occluders. The jump flooding runs on the async compute pool, until it is done the shadows use the
+// old field. A rebuild that gets started while another one runs replaces it.
+pub(crate) fn update_distance_field(
+    time: Res<Time>,
+    illumination: Res<GlobalIllumination>,
+    grid: Res<OccluderGrid>,
+    occluder_q: Query<(&LightOccluder, &GlobalTransform)>,
+    mut rebuild: Local<FieldRebuild>,
+    mut distance_field: ResMut<OccluderDistanceField>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    // The grid only changes when an occluder does
+    if grid.is_changed() {
+        rebuild.last_change = Some(time.elapsed_seconds());
+    }
+    let settled = rebuild.last_change.is_some_and(|last_change| time.elapsed_seconds() - last_change >= REBUILD_DELAY);
+    if rebuild.mode != Some(illumination.shadows) || settled {
+        rebuild.mode = Some(illumination.shadows);
+        rebuild.last_change = None;
+        rebuild.pending = None;
+        match illumination.shadows {
+            ShadowMode::DistanceField { texel_size } => {
+                let occluders: Vec<(LightOccluder, GlobalTransform)> =
+                    occluder_q.iter().map(|(occluder, transform)| (occluder.clone(), *transform)).collect();
+                let (sender, receiver) = mpsc::channel();
+                AsyncComputeTaskPool::get()
+                    .spawn(async move {
+                        let occluders = occluders.iter().map(|(occluder, transform)| (occluder, transform));
+                        // Nobody is waiting anymore if another rebuild started in the meantime
+                        let _ = sender.send(DistanceField::new(occluders, texel_size));
+                    })
+                    .detach();
+                rebuild.pending = Some(Mutex::new(receiver));
+            }
+            ShadowMode::Segments => *distance_field = default(),
+        }
+    }
+
+    let received = match rebuild.pending.as_mut() {
+        Some(receiver) => receiver.get_mut().unwrap().try_recv(),
+        None => return,
+    };
+    match received {
+        Ok(field) => {
+            rebuild.pending = None;
+            *distance_field = match field {
+                Some(field) => OccluderDistanceField {
+                    texture: Some(images.add(field.to_image())),
+                    origin: field.origin,
+                    texel_size: field.texel_size,
+                },
+                None => default(),
+            };
+        }
+        Err(TryRecvError::Empty) => {}
+        // The rebuild panicked, keep the old field
+        Err(TryRecvError::Disconnected) => rebuild.pending = None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lighting::cpu_lighting::{occlusion, transmittance};
+
+    // A long thin wall from (0, 0) to (1000, 2), one world unit per texel. Rects hang down from their position.
+    fn wall_field() -> DistanceField {
+        let wall = LightOccluder::rect(1000.0, 2.0);
+        let transform = GlobalTransform::from_xyz(0.0, 2.0, 0.0);
+        DistanceField::new([(&wall, &transform)], 1.0).unwrap()
+    }
+
+    #[test]
+    fn rays_through_and_past_the_wall() {
+        let field = wall_field();
+        assert_eq!(field.texel_size, 1.0);
+        assert_eq!(field.march(Vec2::new(500.0, -10.0), Vec2::new(500.0, 10.0)), None);
+        assert_eq!(field.march(Vec2::new(500.0, 10.0), Vec2::new(520.0, 40.0)), Some(1.0));
+        assert_eq!(field.march(Vec2::new(5.0, 300.0), Vec2::new(995.0, 300.0)), Some(1.0));
+    }
+
+    #[test]
+    fn only_opaque_levels_without_layers_and_heights_are_supported() {
+        let wall = LightOccluder::rect(10.0, 10.0);
+        let light = LightSource::default();
+        assert!(DistanceField::supports([&wall], [&light]));
+
+        let layered = LightOccluder { layers: 1, ..wall.clone() };
+        let low = LightOccluder { height: Some(20.0), ..wall.clone() };
+        let unlayered_light = LightSource { layers: 0, ..light };
+        assert!(!DistanceField::supports([&wall, &layered], [&light]));
+        assert!(!DistanceField::supports([&low], [&light]));
+        assert!(!DistanceField::supports([&wall], [&unlayered_light]));
+        let translucent = LightOccluder { opacity: 0.5, ..wall.clone() };
+        assert!(!DistanceField::supports([&translucent], [&light]));
+        // Black glass lets nothing through either
+        let black = LightOccluder { transmittance: Color::BLACK, ..translucent.clone() };
+        assert!(DistanceField::supports([&black], [&light]));
+        // Lights on some layers are still blocked by walls on every layer
+        assert!(DistanceField::supports([&wall], [&LightSource { layers: 2, ..light }]));
+    }
+
+    #[test]
+    fn rays_out_of_steps_are_tested_against_the_segments() {
+        let wall = (LightOccluder::rect(1000.0, 2.0), Transform::from_xyz(0.0, 2.0, 0.0));
+        let occlusion = occlusion(&[wall], ShadowMode::DistanceField { texel_size: 1.0 });
+        let field = occlusion.distance_field.as_ref().unwrap();
+        // Right above the wall every step is half a texel, far too few to get to the end
+        let (start, end) = (Vec2::new(5.0, 3.0), Vec2::new(995.0, 3.0));
+        assert!(field.distance(start) < 2.0);
+        assert!(field.march(start, end).unwrap() < 1.0);
+        assert_eq!(transmittance(start, end, ALL_LIGHT_LAYERS, 0.0, &occlusion), Vec3::ONE);
+        // Past the end of the steps the wall still blocks the light
+        let (start, end) = (Vec2::new(5.0, 3.5), Vec2::new(995.0, 1.0));
+        assert!(field.march(start, end).unwrap() < 1.0);
+        assert_eq!(transmittance(start, end, ALL_LIGHT_LAYERS, 0.0, &occlusion), Vec3::ZERO);
+    }
+}
@@ -0,0 +1,90 @@
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use serde::{Deserialize, Serialize};
+
+use super::LightingTextures;
+
+/// How the light of a [`super::LightSource`] fades out towards its radius. Every falloff reaches zero at the radius.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Falloff {
+    /// Inverse square falloff, scaled to the radius and windowed so it ends at the radius
+    #[default]
+    InverseSquare,
+    Linear,
+    Smoothstep,
+    /// Index of a curve in [`FalloffCurves`]
+    Curve(u32),
+}
+
+impl Falloff {
+    /// `falloff` and `curve_index` of `GpuLightSource`
+    pub fn gpu_index(self) -> (u32, u32) {
+        match self {
+            Falloff::InverseSquare => (0, 0),
+            Falloff::Linear => (1, 0),
+            Falloff::Smoothstep => (2, 0),
+            Falloff::Curve(index) => (3, index),
+        }
+    }
+}
+
+/// How many values every curve gets resampled to in the curve texture
+pub const FALLOFF_CURVE_SAMPLES: usize = 64;
+
+/// Custom falloff curves for [`Falloff::Curve`], drawn by the designers. Every curve is spaced evenly from the
+/// light's position (first value) to its radius (last value). They are uploaded as one texture row per curve.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct FalloffCurves {
+    pub curves: Vec<Vec<f32>>,
+}
+
+impl FalloffCurves {
+    /// Every curve resampled to [`FALLOFF_CURVE_SAMPLES`] values, row after row. There is always at least one row.
+    pub fn resampled(&self) -> Vec<f32> {
+        if self.curves.is_empty() {
+            return vec![0.0; FALLOFF_CURVE_SAMPLES];
+        }
+
+        self.curves
+            .iter()
+            .flat_map(|curve| {
+                (0..FALLOFF_CURVE_SAMPLES).map(move |i| {
+                    let Some(last) = curve.len().checked_sub(1) else {
+                        return 0.0;
+                    };
+                    let x = i as f32 / (FALLOFF_CURVE_SAMPLES - 1) as f32 * last as f32;
+                    let x0 = x.floor() as usize;
+                    let x1 = (x0 + 1).min(last);
+                    curve[x0] + (curve[x1] - curve[x0]) * x.fract()
+                })
+            })
+            .collect()
+    }
+
+    pub fn to_image(&self) -> Image {
+        let values = self.resampled();
+        Image::new(
+            Extent3d {
+                width: FALLOFF_CURVE_SAMPLES as u32,
+                height: (values.len() / FALLOFF_CURVE_SAMPLES) as u32,
+                ..default()
+            },
+            TextureDimension::D2,
+            values.iter().flat_map(|value| value.to_le_bytes()).collect(),
+            TextureFormat::R32Float,
+        )
+    }
+}
+
+// Uploads the curves again whenever they change
+pub(crate) fn update_falloff_curve_texture(
+    curves: Res<FalloffCurves>,
+    mut textures: ResMut<LightingTextures>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if curves.is_changed() {
+        textures.falloff_curves = images.add(curves.to_image());
+    }
+}
@@ -0,0 +1,125 @@
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+use serde::{Deserialize, Serialize};
+
+use super::ShadowMode;
+
+/// Light that reaches everything, no matter where the lights are. Indoor maps want a dark, constant ambient,
+/// outdoor maps can let it follow a [`DayNightCycle`].
+#[derive(Resource, ExtractResource, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GlobalIllumination {
+    pub ambient_color: Color,
+    pub ambient_intensity: f32,
+    /// How much the lighting darkens the map, 1 leaves unlit parts at the ambient light and 0 turns lighting off
+    pub darkness: f32,
+    /// Shown around the map, gets darkened just like the map
+    pub background: Color,
+    pub day_night: Option<DayNightCycle>,
+    /// How far the pixels of the emissive map light up the map around them, 0 keeps the glow to the pixels themselves
+    pub emissive_spread: f32,
+    /// Strength of the light spilled by emissive pixels
+    pub emissive_light: f32,
+    pub shadows: ShadowMode,
+}
+
+impl Default for GlobalIllumination {
+    fn default() -> Self {
+        Self {
+            ambient_color: Color::WHITE,
+            ambient_intensity: 0.1,
+            darkness: 1.0,
+            background: Color::rgb(0.4, 0.4, 0.4),
+            day_night: None,
+            emissive_spread: 0.0,
+            emissive_light: 1.0,
+            shadows: ShadowMode::Segments,
+        }
+    }
+}
+
+impl GlobalIllumination {
+    /// Linear ambient light right now, following the day night cycle if there is one
+    pub fn ambient(&self) -> Vec3 {
+        match &self.day_night {
+            Some(cycle) if !cycle.keyframes.is_empty() => cycle.ambient(),
+            _ => linear_rgb(self.ambient_color) * self.ambient_intensity,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct AmbientKeyframe {
+    /// Time of day from 0 to 1
+    pub time: f32,
+    pub color: Color,
+    pub intensity: f32,
+}
+
+/// Animates the ambient light over a day
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DayNightCycle {
+    /// Length of a whole day in seconds
+    pub day_length: f32,
+    /// From 0 to 1, 0 is midnight and 0.5 is noon
+    pub time_of_day: f32,
+    pub paused: bool,
+    /// Sorted by time, the ambient light gets interpolated between them and wraps around at midnight
+    pub keyframes: Vec<AmbientKeyframe>,
+}
+
+impl Default for DayNightCycle {
+    fn default() -> Self {
+        let keyframe = |time, color, intensity| AmbientKeyframe { time, color, intensity };
+        Self {
+            day_length: 120.0,
+            time_of_day: 0.5,
+            paused: false,
+            keyframes: vec![
+                keyframe(0.0, Color::rgb(0.3, 0.4, 1.0), 0.05),
+                keyframe(0.25, Color::rgb(1.0, 0.6, 0.4), 0.4),
+                keyframe(0.5, Color::WHITE, 1.0),
+                keyframe(0.75, Color::rgb(1.0, 0.5, 0.3), 0.4),
+            ],
+        }
+    }
+}
+
+impl DayNightCycle {
+    pub fn ambient(&self) -> Vec3 {
+        let light = |keyframe: &AmbientKeyframe| linear_rgb(keyframe.color) * keyframe.intensity;
+
+        let next = self.keyframes.iter().position(|keyframe| keyframe.time > self.time_of_day).unwrap_or(0);
+        let previous = (next + self.keyframes.len() - 1) % self.keyframes.len();
+        let (from, to) = (&self.keyframes[previous], &self.keyframes[next]);
+
+        // Times are relative to `from`, so wrapping around midnight works out
+        let span = (to.time - from.time).rem_euclid(1.0);
+        let elapsed = (self.time_of_day - from.time).rem_euclid(1.0);
+        let t = if span > 0.0 { elapsed / span } else { 0.0 };
+
+        light(from).lerp(light(to), t)
+    }
+}
+
+fn linear_rgb(color: Color) -> Vec3 {
+    Vec4::from(color.as_linear_rgba_f32()).truncate()
+}
+
+pub(crate) fn update_global_illumination(
+    time: Res<Time>,
+    mut illumination: ResMut<GlobalIllumination>,
+    mut clear_color: ResMut<ClearColor>,
+) {
+    // Only borrow mutably while the cycle runs, so the resource doesn't count as changed otherwise
+    let running = matches!(&illumination.day_night, Some(cycle) if !cycle.paused && cycle.day_length > 0.0);
+    if running {
+        if let Some(cycle) = illumination.day_night.as_mut() {
+            cycle.time_of_day = (cycle.time_of_day + time.delta_seconds() / cycle.day_length).rem_euclid(1.0);
+        }
+    }
+
+    // The lighting pass lights the background like everything else
+    if illumination.is_changed() {
+        clear_color.0 = illumination.background;
+    }
+}
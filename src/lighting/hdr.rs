@@ -0,0 +1,99 @@
+use bevy::{
+    core_pipeline::{
+        bloom::{BloomCompositeMode, BloomPrefilterSettings, BloomSettings},
+        tonemapping::Tonemapping,
+    },
+    prelude::*,
+    render::view::ColorGrading,
+};
+
+use super::LightingCamera;
+
+/// How the light gets from the HDR view to the screen. The lights add up without being clamped, parts brighter than
+/// `bloom_threshold` bloom and the tonemapper squeezes everything into what the screen can show.
+#[derive(Resource, Clone, Debug)]
+pub struct HdrSettings {
+    /// In stops, every stop doubles the brightness before tonemapping
+    pub exposure: f32,
+    pub tonemapping: Tonemapping,
+    /// Brightness above which the view blooms, 1 keeps the bloom to lights brighter than white
+    pub bloom_threshold: f32,
+    /// How much of the bloom gets added to the view, 0 turns bloom off
+    pub bloom_intensity: f32,
+}
+
+impl Default for HdrSettings {
+    fn default() -> Self {
+        Self { exposure: 0.0, tonemapping: Tonemapping::TonyMcMapface, bloom_threshold: 1.0, bloom_intensity: 0.15 }
+    }
+}
+
+impl HdrSettings {
+    /// `None` when bloom is off
+    pub fn bloom(&self) -> Option<BloomSettings> {
+        if self.bloom_intensity <= 0.0 {
+            return None;
+        }
+
+        Some(BloomSettings {
+            intensity: self.bloom_intensity,
+            prefilter_settings: BloomPrefilterSettings { threshold: self.bloom_threshold, threshold_softness: 0.2 },
+            // Bevy recommends additive bloom together with a threshold
+            composite_mode: BloomCompositeMode::Additive,
+            ..BloomSettings::NATURAL
+        })
+    }
+}
+
+// Applies the settings to every lit camera, whenever they change or a camera is added
+pub(crate) fn update_hdr_settings(
+    mut commands: Commands,
+    settings: Res<HdrSettings>,
+    camera_q: Query<Entity, With<LightingCamera>>,
+    added_q: Query<(), Added<LightingCamera>>,
+) {
+    if !settings.is_changed() && added_q.is_empty() {
+        return;
+    }
+
+    for entity in camera_q.iter() {
+        let mut camera = commands.entity(entity);
+        camera.insert((settings.tonemapping, ColorGrading { exposure: settings.exposure, ..default() }));
+        match settings.bloom() {
+            Some(bloom) => camera.insert(bloom),
+            None => camera.remove::<BloomSettings>(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_reach_the_lit_cameras() {
+        let mut app = App::new();
+        app.insert_resource(HdrSettings {
+            exposure: 1.5,
+            tonemapping: Tonemapping::AcesFitted,
+            bloom_threshold: 0.8,
+            bloom_intensity: 0.3,
+        })
+        .add_system(update_hdr_settings);
+        let camera = app.world.spawn(LightingCamera).id();
+        let unlit = app.world.spawn_empty().id();
+        app.update();
+
+        let lit = app.world.entity(camera);
+        assert_eq!(lit.get::<ColorGrading>().unwrap().exposure, 1.5);
+        assert_eq!(lit.get::<Tonemapping>(), Some(&Tonemapping::AcesFitted));
+        let bloom = lit.get::<BloomSettings>().unwrap();
+        assert_eq!(bloom.intensity, 0.3);
+        assert_eq!(bloom.prefilter_settings.threshold, 0.8);
+        assert!(app.world.entity(unlit).get::<ColorGrading>().is_none());
+
+        app.world.resource_mut::<HdrSettings>().bloom_intensity = 0.0;
+        app.update();
+        assert!(app.world.entity(camera).get::<BloomSettings>().is_none());
+    }
+}
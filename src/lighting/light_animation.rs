@@ -0,0 +1,334 @@
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::GameState;
+
+use super::{hash, LightSource};
+
+/// Animates lights over time. Every animation is a pure function of the elapsed time (and its seed), so the same
+/// animation always produces the same values at the same time.
+///
+/// The authored values of a [`LightSource`] are never touched, so they can still be edited in the inspector while
+/// the light is animated. The animation only sets the light's [`LightModulation`], which gets applied to the light
+/// when it is extracted for rendering.
+pub struct LightAnimationPlugin;
+
+impl Plugin for LightAnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems((animate_lights, remove_stopped_modulations).in_set(OnUpdate(GameState::Playing)));
+    }
+}
+
+/// Everything animating a light. All of the animations can be combined, flicker and pulse multiply.
+#[derive(Component, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LightAnimation {
+    #[serde(default)]
+    pub flicker: Option<LightFlicker>,
+    #[serde(default)]
+    pub pulse: Option<LightPulse>,
+    #[serde(default)]
+    pub gradient: Option<LightGradient>,
+    #[serde(default)]
+    pub blink: Option<LightBlink>,
+}
+
+impl LightAnimation {
+    /// What the animation does to the light at `time` seconds
+    pub fn sample(&self, time: f32) -> LightModulation {
+        let mut modulation = LightModulation::default();
+        if let Some(flicker) = &self.flicker {
+            let (intensity, radius) = flicker.sample(time);
+            modulation.intensity *= intensity;
+            modulation.radius *= radius;
+        }
+        if let Some(pulse) = &self.pulse {
+            let (intensity, radius) = pulse.sample(time);
+            modulation.intensity *= intensity;
+            modulation.radius *= radius;
+        }
+        if let Some(color) = self.gradient.as_ref().and_then(|gradient| gradient.sample(time)) {
+            modulation.color = color;
+        }
+        if let Some(blink) = &self.blink {
+            modulation.is_on = blink.sample(time);
+        }
+        modulation
+    }
+}
+
+/// How an animation changes a light right now, kept up to date by `animate_lights`. Lights without an animation
+/// don't have one.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct LightModulation {
+    /// Multiplies the intensity
+    pub intensity: f32,
+    /// Multiplies the radius
+    pub radius: f32,
+    /// Multiplies the color
+    pub color: Vec4,
+    /// Switches the light off without touching [`LightSource::is_active`]
+    pub is_on: bool,
+}
+
+impl Default for LightModulation {
+    fn default() -> Self {
+        Self { intensity: 1.0, radius: 1.0, color: Vec4::ONE, is_on: true }
+    }
+}
+
+impl LightModulation {
+    /// The light as it gets rendered
+    pub fn apply(&self, light: &LightSource) -> LightSource {
+        LightSource {
+            intensity: light.intensity * self.intensity,
+            radius: light.radius * self.radius,
+            color: light.color * self.color,
+            is_active: light.is_active * self.is_on as u32,
+            ..*light
+        }
+    }
+}
+
+/// Random flickering of the intensity and radius, like a torch
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LightFlicker {
+    pub seed: u64,
+    /// How many times per second the flicker picks a new target
+    pub frequency: f32,
+    /// How far below the authored intensity and radius the flicker dips, from 0 to 1
+    pub amount: f32,
+}
+
+impl Default for LightFlicker {
+    fn default() -> Self {
+        Self { seed: 0, frequency: 8.0, amount: 0.3 }
+    }
+}
+
+impl LightFlicker {
+    /// A flicker with a random seed, so torches next to each other don't flicker in sync
+    pub fn random() -> Self {
+        Self { seed: rand::thread_rng().gen(), ..default() }
+    }
+
+    /// Factors for the intensity and radius at `time` seconds
+    pub fn sample(&self, time: f32) -> (f32, f32) {
+        let dip = self.amount * value_noise(self.seed, time * self.frequency);
+        (1.0 - dip, 1.0 - dip * 0.5)
+    }
+}
+
+// Random values between 0 and 1 at every whole `x`, smoothly interpolated in between. Uses the same PCG hash as the
+// shader, which unlike the generators of `rand` gives the same values on every platform and version.
+fn value_noise(seed: u64, x: f32) -> f32 {
+    let seed = hash(seed as u32 ^ hash((seed >> 32) as u32));
+    let random = |cell: f32| hash(seed ^ hash(cell as i32 as u32)) as f32 / u32::MAX as f32;
+
+    let cell = x.floor();
+    let t = x - cell;
+    let t = t * t * (3.0 - 2.0 * t);
+    random(cell) + (random(cell + 1.0) - random(cell)) * t
+}
+
+/// Smooth sine wave on the intensity and radius
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LightPulse {
+    /// Pulses per second
+    pub frequency: f32,
+    /// Shifts the wave, from 0 to 1
+    pub phase: f32,
+    /// How far the intensity swings around the authored one, as a fraction of it
+    pub intensity_amplitude: f32,
+    /// How far the radius swings around the authored one, as a fraction of it
+    pub radius_amplitude: f32,
+}
+
+impl Default for LightPulse {
+    fn default() -> Self {
+        Self { frequency: 0.5, phase: 0.0, intensity_amplitude: 0.5, radius_amplitude: 0.0 }
+    }
+}
+
+impl LightPulse {
+    /// Factors for the intensity and radius at `time` seconds
+    pub fn sample(&self, time: f32) -> (f32, f32) {
+        let wave = ((time * self.frequency + self.phase) * std::f32::consts::TAU).sin();
+        ((1.0 + self.intensity_amplitude * wave).max(0.0), (1.0 + self.radius_amplitude * wave).max(0.0))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColorKeyframe {
+    /// Seconds from the start of the gradient
+    pub time: f32,
+    pub color: Vec4,
+}
+
+/// Cycles the color through keyframes, linearly interpolated. The keyframes tint the authored color, so on a white
+/// light they are the colors that get rendered.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LightGradient {
+    /// Sorted by time
+    pub keyframes: Vec<ColorKeyframe>,
+    /// Starts over after the last keyframe instead of holding it
+    pub looping: bool,
+}
+
+impl LightGradient {
+    /// Color at `time` seconds, `None` without keyframes
+    pub fn sample(&self, time: f32) -> Option<Vec4> {
+        let (first, last) = (self.keyframes.first()?, self.keyframes.last()?);
+        let duration = last.time - first.time;
+        let time =
+            if self.looping && duration > 0.0 { first.time + (time - first.time).rem_euclid(duration) } else { time };
+
+        let next = self.keyframes.iter().position(|keyframe| keyframe.time > time);
+        let color = match next {
+            None => last.color,
+            Some(0) => first.color,
+            Some(next) => {
+                let (from, to) = (&self.keyframes[next - 1], &self.keyframes[next]);
+                from.color.lerp(to.color, (time - from.time) / (to.time - from.time))
+            }
+        };
+        Some(color)
+    }
+
+    /// Red, green and blue, one second each
+    pub fn rainbow() -> Self {
+        let keyframe = |time: f32, color: Vec4| ColorKeyframe { time, color };
+        Self {
+            keyframes: vec![
+                keyframe(0.0, Vec4::new(1.0, 0.0, 0.0, 1.0)),
+                keyframe(1.0, Vec4::new(0.0, 1.0, 0.0, 1.0)),
+                keyframe(2.0, Vec4::new(0.0, 0.0, 1.0, 1.0)),
+                keyframe(3.0, Vec4::new(1.0, 0.0, 0.0, 1.0)),
+            ],
+            looping: true,
+        }
+    }
+}
+
+/// Switches the light on and off following a pattern, e.g. a broken neon tube
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LightBlink {
+    /// Seconds every step of the pattern lasts
+    pub step: f32,
+    /// Whether the light is on during each step, repeats forever
+    pub pattern: Vec<bool>,
+}
+
+impl Default for LightBlink {
+    fn default() -> Self {
+        Self {
+            step: 0.1,
+            pattern: vec![true, true, true, true, false, true, false, true, true, true, true, true, false, false],
+        }
+    }
+}
+
+impl LightBlink {
+    /// Is the light on at `time` seconds? Lights without a pattern stay on.
+    pub fn sample(&self, time: f32) -> bool {
+        if self.pattern.is_empty() || self.step <= 0.0 {
+            return true;
+        }
+        let index = (time / self.step).floor().rem_euclid(self.pattern.len() as f32) as usize;
+        self.pattern[index.min(self.pattern.len() - 1)]
+    }
+}
+
+fn animate_lights(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut light_q: Query<(Entity, &LightAnimation, Option<&mut LightModulation>)>,
+) {
+    for (entity, animation, modulation) in light_q.iter_mut() {
+        let sampled = animation.sample(time.elapsed_seconds());
+        match modulation {
+            Some(mut modulation) => *modulation = sampled,
+            None => {
+                commands.entity(entity).insert(sampled);
+            }
+        }
+    }
+}
+
+// Lights go back to their authored values once their animation is taken away
+fn remove_stopped_modulations(mut commands: Commands, mut removed: RemovedComponents<LightAnimation>) {
+    for entity in removed.iter() {
+        if let Some(mut entity) = commands.get_entity(entity) {
+            entity.remove::<LightModulation>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flicker_is_deterministic() {
+        let flicker = LightFlicker { seed: 42, ..default() };
+        let samples: Vec<(f32, f32)> = (0..20).map(|i| flicker.sample(i as f32 * 0.37)).collect();
+        let again: Vec<(f32, f32)> = (0..20).map(|i| flicker.sample(i as f32 * 0.37)).collect();
+        assert_eq!(samples, again);
+
+        // Pinned, so a change to the noise shows up here and not only as torches flickering differently
+        assert_eq!(value_noise(42, 0.0), 0.7886072);
+        assert_eq!(value_noise(42, 1.5), 0.534287);
+        assert_eq!(value_noise(u64::MAX, -3.25), 0.25233597);
+        let (intensity, radius) = flicker.sample(0.0);
+        let dip = 0.3 * value_noise(42, 0.0);
+        assert_eq!((intensity, radius), (1.0 - dip, 1.0 - dip * 0.5));
+    }
+
+    #[test]
+    fn flicker_seeds_differ_and_stay_in_range() {
+        let a = LightFlicker { seed: 1, ..default() };
+        let b = LightFlicker { seed: 2, ..default() };
+        assert!((0..50).any(|i| a.sample(i as f32 * 0.1) != b.sample(i as f32 * 0.1)));
+        for i in 0..200 {
+            let (intensity, radius) = a.sample(i as f32 * 0.05);
+            assert!((0.7..=1.0).contains(&intensity), "{intensity}");
+            assert!((0.85..=1.0).contains(&radius), "{radius}");
+        }
+    }
+
+    #[test]
+    fn pulse_blink_and_gradient_sample_exact_values() {
+        let pulse = LightPulse::default();
+        assert_eq!(pulse.sample(0.0), (1.0, 1.0));
+        // A quarter of the period of 2 seconds is the top of the wave
+        assert_eq!(pulse.sample(0.5), (1.5, 1.0));
+
+        let blink = LightBlink { step: 0.5, pattern: vec![true, false] };
+        assert!(blink.sample(0.25));
+        assert!(!blink.sample(0.75));
+        assert!(blink.sample(1.25));
+
+        let gradient = LightGradient::rainbow();
+        assert_eq!(gradient.sample(0.5), Some(Vec4::new(0.5, 0.5, 0.0, 1.0)));
+        // Loops after 3 seconds
+        assert_eq!(gradient.sample(3.5), gradient.sample(0.5));
+    }
+
+    #[test]
+    fn modulation_keeps_the_authored_light() {
+        let light = LightSource { color: Vec4::ONE, intensity: 2.0, radius: 100.0, is_active: 1, ..default() };
+        let animation = LightAnimation {
+            pulse: Some(LightPulse::default()),
+            blink: Some(LightBlink { step: 1.0, pattern: vec![false] }),
+            ..default()
+        };
+
+        let rendered = animation.sample(0.5).apply(&light);
+        assert_eq!(rendered.intensity, 3.0);
+        assert_eq!(rendered.radius, 100.0);
+        assert_eq!(rendered.is_active, 0);
+        // An inactive light stays off no matter what the animation says
+        let inactive = LightSource { is_active: 0, ..light };
+        assert_eq!(LightAnimation::default().sample(0.0).apply(&inactive).is_active, 0);
+    }
+}
@@ -1,28 +1,21 @@
-//! A custom post processing effect, using two cameras, with one reusing the render texture of the first one.
-//! Here a chromatic aberration is applied to a 3d scene containing a rotating cube.
-//! This example is useful to implement your own post-processing effect such as
-//! edge detection, blur, pixelization, vignette... and countless others.
+//! The resources the lighting pass reads and how they are packed into its buffers. The pass itself is in
+//! `lighting_plugin.rs`.
 
 use bevy::{
-    core_pipeline::clear_color::ClearColorConfig,
     prelude::*,
-    reflect::TypeUuid,
     render::{
-        camera::RenderTarget,
-        render_resource::{
-            AsBindGroup, Extent3d, ShaderRef, TextureDescriptor, TextureDimension, TextureFormat,
-            TextureUsages, ShaderType, OwnedBindingResource, encase,
-        },
-        texture::BevyDefault,
-        view::RenderLayers, RenderApp, RenderSet, Extract, renderer::RenderQueue,
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_resource::{encase, ShaderType},
     },
-    sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle, RenderMaterials2d}, transform, 
 };
-use bevy_pancam::PanCam;
 
-use crate::{camera::{MainCamera, setup_camera}, map::MapMarker};
+use crate::camera::setup_camera;
 
-use super::{LightSource, LightOccluder};
+use super::{
+    update_distance_field, update_falloff_curve_texture, update_global_illumination, update_hdr_settings,
+    update_occluder_grid, BakedLighting, FalloffCurves, GlobalIllumination, HdrSettings, LightOccluder, LightSource,
+    LightingPlugin, OccluderDistanceField, OccluderGrid,
+};
 
 #[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
 pub enum CameraSet {
@@ -30,347 +23,339 @@ pub enum CameraSet {
     LightingSetup
 }
 
+/// How many pixels of the view share one texel of the light texture along each axis. The light is rendered at
+/// this resolution, then bilinearly upsampled and multiplied over the view. Lower resolutions are faster on weak
+/// GPUs, but blur the shadow edges and the detail of the normal map.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LightResolution {
+    #[default]
+    Full,
+    Half,
+    Quarter,
+}
+
+impl LightResolution {
+    pub const ALL: [LightResolution; 3] = [LightResolution::Full, LightResolution::Half, LightResolution::Quarter];
+
+    pub fn divisor(self) -> u32 {
+        match self {
+            LightResolution::Full => 1,
+            LightResolution::Half => 2,
+            LightResolution::Quarter => 4,
+        }
+    }
+}
+
+/// Quality settings of the lighting pass, these can be changed at any time, e.g. from the settings panel
+#[derive(Resource, ExtractResource, Clone, Copy, Debug, Default)]
+pub struct LightingQuality {
+    pub resolution: LightResolution,
+}
+
 pub struct LightingPostprocessPlugin;
 
 impl Plugin for LightingPostprocessPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugin(Material2dPlugin::<LightingMaterial>::default())
+        app.add_plugin(LightingPlugin)
+            .init_resource::<FalloffCurves>()
+            .init_resource::<GlobalIllumination>()
+            .init_resource::<BakedLighting>()
+            .init_resource::<OccluderGrid>()
+            .init_resource::<OccluderDistanceField>()
+            .init_resource::<LightingQuality>()
+            .init_resource::<HdrSettings>()
+            .add_plugin(ExtractResourcePlugin::<GlobalIllumination>::default())
+            .add_plugin(ExtractResourcePlugin::<BakedLighting>::default())
+            .add_plugin(ExtractResourcePlugin::<OccluderGrid>::default())
+            .add_plugin(ExtractResourcePlugin::<OccluderDistanceField>::default())
+            .add_plugin(ExtractResourcePlugin::<LightingQuality>::default())
+            .add_system(update_falloff_curve_texture)
+            .add_system(update_occluder_grid)
+            .add_system(update_distance_field.after(update_occluder_grid))
+            .add_system(update_global_illumination)
+            .add_system(update_hdr_settings)
             .add_startup_system(setup.in_set(CameraSet::LightingSetup).after(CameraSet::CameraSetup));
-
-        app.sub_app_mut(RenderApp)
-            .add_system(extract_lights.in_schedule(ExtractSchedule).in_set(RenderSet::ExtractCommands))
-            .add_system(extract_occluders.in_schedule(ExtractSchedule).in_set(RenderSet::ExtractCommands))
-            .add_system(prepare_light_material.in_set(RenderSet::Prepare));
     }
 }
 
-#[derive(Component)]
-pub struct OriginalCamera;
-
-fn extract_lights(
-    mut commands: Commands,
-    mut light_q: Extract<Query<(Entity, &LightSource, &GlobalTransform)>>, 
-    mut materials_q: Extract<Query<(Entity, &Handle<LightingMaterial>)>>,
-    mut cam_info: Extract<Query<(Entity, &PanCam, &Transform, &GlobalTransform, &OrthographicProjection, &Camera)>>,
-) {
-    for (entity, light_source, global_trans) in &light_q {
-        commands.get_or_spawn(entity)
-            .insert(*light_source)
-            .insert(*global_trans);
-    }
+// Native targets get runtime sized storage buffers, so every light and occluder segment is uploaded.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn pack_lighting_buffers(lights: &[GpuLightSource], segments: &[GpuSegment]) -> (Vec<u8>, Vec<u8>, LightingGlobals) {
+    let mut lights_buffer = encase::StorageBuffer::new(Vec::new());
+    lights_buffer.write(&padded(lights)).unwrap();
 
-    for (entity, material) in &materials_q {
-        commands.get_or_spawn(entity)
-            .insert(material.clone());
-    }
+    let mut segments_buffer = encase::StorageBuffer::new(Vec::new());
+    segments_buffer.write(&padded(segments)).unwrap();
 
-    for (entity, pan_cam, trans, global_trans, proj, camera) in &cam_info {
-        commands.get_or_spawn(entity)
-            .insert(OriginalCamera)
-            .insert(*trans)
-            .insert(proj.clone())
-            .insert(*global_trans)
-            .insert(camera.clone());
-    }
+    let globals = LightingGlobals {
+        light_count: lights.len() as u32,
+        segment_count: segments.len() as u32,
+        ..default()
+    };
+
+    (lights_buffer.into_inner(), segments_buffer.into_inner(), globals)
 }
 
-fn extract_occluders(
-    mut commands: Commands,
-    mut occluder_q: Extract<Query<(Entity, &LightOccluder, &GlobalTransform)>>,
-) {
-    for (entity, occl, trans) in &occluder_q {
-        commands.get_or_spawn(entity)
-            .insert(*occl)
-            .insert(*trans);
-    }
+// WebGL2 has no storage buffers, so we fall back to fixed size uniform arrays and drop whatever doesn't fit.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn pack_lighting_buffers(lights: &[GpuLightSource], segments: &[GpuSegment]) -> (Vec<u8>, Vec<u8>, LightingGlobals) {
+    pack_uniform_lighting_buffers(lights, segments)
 }
 
-fn prepare_light_material(
-    materials: Res<RenderMaterials2d<LightingMaterial>>,
-    mut light_sources: Query<(&LightSource, &GlobalTransform)>,
-    mut material_q: Query<(&Handle<LightingMaterial>)>,
-    mut camera_q: Query<(&OriginalCamera, &Transform, &GlobalTransform, &OrthographicProjection, &Camera)>,
-    mut occluder_q: Query<(&LightOccluder, &GlobalTransform)>,
-    render_queue: Res<RenderQueue>,
-) {
-    if let Ok(cam) = camera_q.get_single_mut() {
-        let (p , cam_trans, global_trans, cam_proj, camera) = cam;
-
-        for mat in material_q.iter_mut() {
-            if let Some(mut light_material) = materials.get(mat) {
-                let mut lighting_uniform_data = LightingMaterialUniformData {
-                    colors: [WrappedVec4 {
-                        value: Vec4::ZERO,
-                    }; 64],
-                    positions: [WrappedVec2 {
-                        value: Vec2::ZERO,
-                    }; 64],
-                    intensities: [WrappedF32 {
-                        value: 0.0,
-                    }; 64],
-                    radius: [WrappedF32 {
-                        value: 0.0,
-                    }; 64],
-                    is_active: [WrappedBool {
-                        value: 0,
-                    }; 64]
-                };
-            
-                let mut i = 0;
-                for (light_source, light_global_trans) in light_sources.iter_mut() {
-                    lighting_uniform_data.colors[i] = WrappedVec4 {
-                        value: light_source.color,
-                    };
-                    lighting_uniform_data.positions[i] = WrappedVec2 {
-                        value: light_global_trans.translation().truncate()
-                    };
-                    lighting_uniform_data.intensities[i] = WrappedF32 {
-                        value: light_source.intensity,
-                    }; 
-                    lighting_uniform_data.is_active[i] = WrappedBool {
-                        value: light_source.is_active,
-                    }; 
-                    lighting_uniform_data.radius[i] = WrappedF32 {
-                        value: light_source.radius,
-                    }; 
-                    
-                    i += 1;
-                }
-                if let Some(binding) = light_material.bindings.get(2) {
-                    if let OwnedBindingResource::Buffer(cur_buffer) = binding {
-                        let mut buffer = encase::UniformBuffer::new(Vec::new());
-                        buffer.write(&lighting_uniform_data).unwrap();
-                        render_queue.write_buffer(cur_buffer, 0, buffer.as_ref());
-                    }
-                }
-
-                let mut occluder_uniform_data = OccluderMaterialUniformData {
-                    exists: [WrappedBool {
-                        value: 0,
-                    }; 64],
-                    occluders: [WrappedVec4 {
-                        value: Vec4::ZERO,
-                    }; 64],
-                };
-
-
-                let mut i = 0;
-                for (occluder, trans) in occluder_q.iter_mut() {
-                    let p1 = trans.translation().truncate();
-                    let rect: Vec4 = Vec4::new(p1.x, p1.x + occluder.width, p1.y , p1.y - occluder.height);
-                    occluder_uniform_data.exists[i] = WrappedBool {
-                        value: 1,
-                    };
-                    occluder_uniform_data.occluders[i] = WrappedVec4 {
-                        value: rect
-                    };
-                    
-                    i += 1;
-                }
-
-                if let Some(binding) = light_material.bindings.get(3) {
-                    if let OwnedBindingResource::Buffer(cur_buffer) = binding {
-                        let mut buffer = encase::UniformBuffer::new(Vec::new());
-                        buffer.write(&occluder_uniform_data).unwrap();
-                        render_queue.write_buffer(cur_buffer, 0, buffer.as_ref());
-                    }
-                }
-            }
-        }
-       
+// Also built for the tests, so the WebGL2 limits are checked on native targets
+#[cfg(any(target_arch = "wasm32", test))]
+fn pack_uniform_lighting_buffers(lights: &[GpuLightSource], segments: &[GpuSegment]) -> (Vec<u8>, Vec<u8>, LightingGlobals) {
+    if lights.len() > MAX_PACKED_LIGHTS || segments.len() > MAX_PACKED_SEGMENTS {
+        warn!(
+            "WebGL2 lighting supports at most {} lights and {} occluder segments, got {} and {}",
+            MAX_PACKED_LIGHTS,
+            MAX_PACKED_SEGMENTS,
+            lights.len(),
+            segments.len()
+        );
     }
-}
 
-fn setup(
-    mut commands: Commands,
-    windows: Query<&Window>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut post_processing_materials: ResMut<Assets<LightingMaterial>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut images: ResMut<Assets<Image>>,
-) {
-    // This assumes we only have a single window
-    let window = windows.single();
-
-    let size = Extent3d {
-        width: window.resolution.physical_width(),
-        height: window.resolution.physical_height(),
-        ..default()
-    };
+    let mut packed_lights = PackedLights::default();
+    let light_count = lights.len().min(MAX_PACKED_LIGHTS);
+    packed_lights.values[..light_count].copy_from_slice(&lights[..light_count]);
+
+    let mut packed_segments = PackedSegments::default();
+    let segment_count = segments.len().min(MAX_PACKED_SEGMENTS);
+    packed_segments.values[..segment_count].copy_from_slice(&segments[..segment_count]);
+
+    let mut lights_buffer = encase::UniformBuffer::new(Vec::new());
+    lights_buffer.write(&packed_lights).unwrap();
 
-    // This is the texture that will be rendered to.
-    let mut image = Image {
-        texture_descriptor: TextureDescriptor {
-            label: None,
-            size,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::bevy_default(),
-            mip_level_count: 1,
-            sample_count: 1,
-            usage: TextureUsages::TEXTURE_BINDING
-                | TextureUsages::COPY_DST
-                | TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[],
-        },
+    let mut segments_buffer = encase::UniformBuffer::new(Vec::new());
+    segments_buffer.write(&packed_segments).unwrap();
+
+    let globals = LightingGlobals {
+        light_count: light_count as u32,
+        segment_count: segment_count as u32,
         ..default()
     };
 
-    // fill image.data with zeroes
-    image.resize(size);
-
-    let image_handle = images.add(image);
-
-    // // Main camera, first to render
-    // commands.spawn((
-    //     Camera3dBundle {
-    //         camera_3d: Camera3d {
-    //             clear_color: ClearColorConfig::Custom(Color::WHITE),
-    //             ..default()
-    //         },
-    //         camera: Camera {
-    //             target: RenderTarget::Image(image_handle.clone()),
-    //             ..default()
-    //         },
-    //         transform: Transform::from_translation(Vec3::new(0.0, 0.0, 15.0))
-    //             .looking_at(Vec3::default(), Vec3::Y),
-    //         ..default()
-    //     },
-    //     // Disable UI rendering for the first pass camera. This prevents double rendering of UI at
-    //     // the cost of rendering the UI without any post processing effects.
-    //     UiCameraConfig { show_ui: false },
-    // ));
-
-    // Setup Camera passing RenderTarget
-    setup_camera(&mut commands, RenderTarget::Image(image_handle.clone()));
-
-
-
-
-    // // This specifies the layer used for the post processing camera, which will be attached to the post processing camera and 2d quad.
-    // let post_processing_pass_layer = RenderLayers::layer((RenderLayers::TOTAL_LAYERS - 1) as u8);
-
-    // let quad_handle = meshes.add(Mesh::from(shape::Quad::new(Vec2::new(
-    //     size.width as f32,
-    //     size.height as f32,
-    // ))));
-
-    // // This material has the texture that has been rendered.
-    // let material_handle = post_processing_materials.add(LightingMaterial {
-    //     colors: [WrappedVec4 {
-    //         value: Vec4::ZERO,
-    //     }; 64],
-    //     positions: [WrappedVec2 {
-    //         value: Vec2::ZERO,
-    //     }; 64],
-    //     intensities: [WrappedF32 {
-    //         value: 0.0,
-    //     }; 64],
-    //     radiuses: [WrappedF32 {
-    //         value: 0.0,
-    //     }; 64],
-    //     is_active: [WrappedBool {
-    //         value: 0,
-    //     }; 64],
-    //     source_image: image_handle,
-    // });
-
-    // // Post processing 2d quad, with material using the render texture done by the main camera, with a custom shader.
-    // commands.spawn((
-    //     MaterialMesh2dBundle {
-    //         mesh: quad_handle.into(),
-    //         material: material_handle,
-    //         transform: Transform {
-    //             translation: Vec3::new(0.0, 0.0, 1.5),
-    //             ..default()
-    //         },
-    //         ..default()
-    //     },
-    //     post_processing_pass_layer,
-    // ));
-
-    // The post-processing pass camera.
-    // commands.spawn((
-    //     Camera2dBundle {
-    //         camera: Camera {
-    //             // renders after the first main camera which has default value: 0.
-    //             order: 1,
-    //             ..default()
-    //         },
-    //         ..Camera2dBundle::default()
-    //     },
-    //     post_processing_pass_layer,
-    //     ));
+    (lights_buffer.into_inner(), segments_buffer.into_inner(), globals)
 }
 
+// The cells and segment indices of the grid. Uniform arrays of indices would take 16 bytes per index, so WebGL2
+// tests every segment instead.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn pack_grid_buffers(grid: &OccluderGrid) -> (Vec<u8>, Vec<u8>) {
+    let mut cells_buffer = encase::StorageBuffer::new(Vec::new());
+    cells_buffer.write(&padded(&grid.cells)).unwrap();
 
-/// Our custom post processing material
-#[derive(AsBindGroup, TypeUuid, Clone)]
-#[uuid = "bc2f08eb-a0fb-43f1-a908-54871ea597d5"]
-pub struct LightingMaterial {
-    /// In this example, this image will be the result of the main camera.
-
-    #[texture(0)]
-    #[sampler(1)]
-    pub source_image: Handle<Image>,
-
-    #[uniform(2)]
-    pub positions: [WrappedVec2; 64],
-    #[uniform(2)]
-    pub colors: [WrappedVec4; 64],
-    #[uniform(2)]
-    pub intensities: [WrappedF32; 64],
-    #[uniform(2)]
-    pub radiuses: [WrappedF32; 64],
-    #[uniform(2)]
-    pub is_active: [WrappedBool; 64],
-    #[uniform(3)]
-    pub occluders: [WrappedVec4; 64],
-    #[uniform(3)]
-    pub exists: [WrappedBool; 64]
+    let mut indices_buffer = encase::StorageBuffer::new(Vec::new());
+    indices_buffer.write(&padded(&grid.indices)).unwrap();
 
+    (cells_buffer.into_inner(), indices_buffer.into_inner())
 }
 
-impl Material2d for LightingMaterial {
-    fn fragment_shader() -> ShaderRef {
-        "shaders/material_lighting.wgsl".into()
+// A storage binding can't be empty, so there is always at least one (zeroed) element in the buffer.
+// The shader only reads up to the counts in `LightingGlobals`.
+#[cfg(not(target_arch = "wasm32"))]
+fn padded<T: Copy + Default>(values: &[T]) -> Vec<T> {
+    if values.is_empty() {
+        vec![T::default()]
+    } else {
+        values.to_vec()
     }
 }
 
-#[derive(Clone, Copy, ShaderType)]
-pub struct WrappedF32 {
-    #[size(16)]
-    pub value: f32,
+fn setup(mut commands: Commands) {
+    setup_camera(&mut commands);
 }
 
-#[derive(Clone, Copy, ShaderType)]
-pub struct WrappedVec2 {
-    #[size(16)]
-    pub value: Vec2,
+/// How many lights and occluder segments fit into the uniform fallback used on WebGL2, which only guarantees 16 KiB
+/// per uniform buffer. Keep in sync with `lighting_pass.wgsl`.
+pub const MAX_PACKED_LIGHTS: usize = 128;
+/// 512 × 32 B = 16 KiB, the WebGL2 uniform minimum.
+pub const MAX_PACKED_SEGMENTS: usize = 512;
+
+#[derive(Clone, Copy, Default, ShaderType, Debug)]
+pub struct GpuLightSource {
+    pub color: Vec4,
+    pub position: Vec2,
+    pub intensity: f32,
+    pub radius: f32,
+    pub is_active: u32,
+    pub source_radius: f32,
+    /// See `Falloff::gpu_index`
+    pub falloff: u32,
+    pub curve_index: u32,
+    /// Only used by spotlights, point lights have a `cos_outer` below -1 so they shine in every direction
+    pub direction: Vec2,
+    pub cos_inner: f32,
+    pub cos_outer: f32,
+    /// World space half axes of line and area lights, the light is emitted from
+    /// `position + extent_a * u + extent_b * v` with u and v between -1 and 1. Both are zero for point lights.
+    pub extent_a: Vec2,
+    pub extent_b: Vec2,
+    pub height: f32,
+    /// See [`LightSource::layers`]
+    pub layers: u32,
 }
 
-#[derive(Clone, Copy, ShaderType)]
-pub struct WrappedVec4 {
-    #[size(16)]
-    pub value: Vec4,
+impl GpuLightSource {
+    pub fn new(light_source: &LightSource, transform: &GlobalTransform) -> Self {
+        let (falloff, curve_index) = light_source.falloff.gpu_index();
+        let half_extents = light_source.shape.half_extents();
+        let affine = transform.affine();
+        let (cos_inner, cos_outer) = match light_source.spot {
+            // The shader smoothsteps between the two, which needs them to be apart
+            Some(spot) => {
+                let cos_outer = spot.outer_angle.cos();
+                (spot.inner_angle.cos().max(cos_outer + 0.0001), cos_outer)
+            }
+            None => (2.0, -2.0),
+        };
+        Self {
+            color: light_source.color,
+            position: transform.translation().truncate(),
+            intensity: light_source.intensity,
+            radius: light_source.radius,
+            is_active: light_source.is_active,
+            source_radius: light_source.source_radius,
+            falloff,
+            curve_index,
+            direction: affine.transform_vector3(Vec3::X).truncate().normalize_or_zero(),
+            cos_inner,
+            cos_outer,
+            extent_a: affine.transform_vector3(Vec3::X * half_extents.x).truncate(),
+            extent_b: affine.transform_vector3(Vec3::Y * half_extents.y).truncate(),
+            height: light_source.height,
+            layers: light_source.layers,
+        }
+    }
+}
+
+/// A single world space edge of an occluder, every occluder shape gets flattened into these
+#[derive(Clone, Copy, Default, ShaderType, Debug)]
+pub struct GpuSegment {
+    pub a: Vec2,
+    pub b: Vec2,
+    /// See [`LightOccluder::layers`]
+    pub layers: u32,
+    /// See [`LightOccluder::height`], `f32::MAX` without one
+    pub height: f32,
+    /// What the occluder lets through, see [`GpuSegment::pack_transmittance`]. Rays only get tinted where they first
+    /// cross an occluder, see [`super::is_first_crossing`].
+    pub transmittance: u32,
+    /// Index of the first segment of the same occluder, the segments of an occluder always come in a row. Filled in
+    /// by [`OccluderGrid::new`], and fills the segments of the WebGL2 uniform array up to 32 bytes.
+    pub first: u32,
+}
+
+impl GpuSegment {
+    pub fn from_occluder(occluder: &LightOccluder, transform: &GlobalTransform) -> impl Iterator<Item = Self> {
+        let (layers, height) = (occluder.layers, occluder.height.unwrap_or(f32::MAX));
+        let transmittance = Self::pack_transmittance(occluder.transmitted());
+        occluder
+            .segments(transform)
+            .into_iter()
+            .map(move |(a, b)| Self { a, b, layers, height, transmittance, first: 0 })
+    }
+
+    /// Linear color in 8 bits per channel, like `pack4x8unorm` in WGSL
+    pub fn pack_transmittance(color: Vec3) -> u32 {
+        color
+            .extend(1.0)
+            .to_array()
+            .iter()
+            .enumerate()
+            .fold(0, |packed, (i, channel)| packed | (((channel.clamp(0.0, 1.0) * 255.0).round() as u32) << (i * 8)))
+    }
+
+    /// Like `unpack4x8unorm` in WGSL
+    pub fn unpack_transmittance(&self) -> Vec3 {
+        Vec3::new(
+            (self.transmittance & 0xff) as f32,
+            (self.transmittance >> 8 & 0xff) as f32,
+            (self.transmittance >> 16 & 0xff) as f32,
+        ) / 255.0
+    }
 }
 
-#[derive(Clone, Copy, ShaderType)]
-pub struct WrappedBool {
-    #[size(16)]
-    pub value: u32,
+#[derive(Clone, Copy, Default, ShaderType, Debug)]
+pub struct LightingGlobals {
+    pub light_count: u32,
+    pub segment_count: u32,
+    /// Linear color, see `GlobalIllumination::ambient`
+    pub ambient: Vec3,
+    pub darkness: f32,
+    pub emissive_spread: f32,
+    pub emissive_light: f32,
+    /// See [`OccluderGrid`]
+    pub grid_origin: Vec2,
+    pub grid_cell_size: f32,
+    pub grid_size: UVec2,
+    /// See [`OccluderDistanceField`]
+    pub field_origin: Vec2,
+    pub field_texel_size: f32,
+    /// Size of the map image in world units, the normal, emissive and baked lightmaps cover the same area
+    pub map_size: Vec2,
 }
 
 #[derive(Clone, ShaderType)]
-pub struct LightingMaterialUniformData {
-    pub positions: [WrappedVec2; 64],
-    pub colors: [WrappedVec4; 64],
-    pub intensities: [WrappedF32; 64],
-    pub radius: [WrappedF32; 64],
-    pub is_active: [WrappedBool; 64]
+pub struct PackedLights {
+    pub values: [GpuLightSource; MAX_PACKED_LIGHTS],
+}
+
+impl Default for PackedLights {
+    fn default() -> Self {
+        Self {
+            values: [GpuLightSource::default(); MAX_PACKED_LIGHTS],
+        }
+    }
 }
 
 #[derive(Clone, ShaderType)]
-pub struct OccluderMaterialUniformData {
-    pub occluders: [WrappedVec4; 64],
-    pub exists: [WrappedBool; 64]
-}
\ No newline at end of file
+pub struct PackedSegments {
+    pub values: [GpuSegment; MAX_PACKED_SEGMENTS],
+}
+
+impl Default for PackedSegments {
+    fn default() -> Self {
+        Self {
+            values: [GpuSegment::default(); MAX_PACKED_SEGMENTS],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::ShaderType;
+
+    use super::*;
+    use crate::lighting::ALL_LIGHT_LAYERS;
+
+    // The smallest maximum uniform buffer size WebGL2 allows
+    const WEBGL2_UNIFORM_BUFFER_SIZE: u64 = 16384;
+
+    #[test]
+    fn packed_arrays_fit_webgl2_uniform_buffers() {
+        assert_eq!(GpuSegment::min_size().get(), 32);
+        assert!(PackedLights::min_size().get() <= WEBGL2_UNIFORM_BUFFER_SIZE);
+        assert!(PackedSegments::min_size().get() <= WEBGL2_UNIFORM_BUFFER_SIZE);
+        // One more segment wouldn't fit
+        assert!(PackedSegments::min_size().get() + GpuSegment::min_size().get() > WEBGL2_UNIFORM_BUFFER_SIZE);
+    }
+
+    #[test]
+    fn uniform_packing_drops_what_does_not_fit() {
+        let segment = GpuSegment {
+            b: Vec2::X,
+            layers: ALL_LIGHT_LAYERS,
+            height: f32::MAX,
+            ..default()
+        };
+        let (lights, segments, globals) = pack_uniform_lighting_buffers(&[GpuLightSource::default(); 200], &[segment; 600]);
+        assert_eq!(globals.light_count, MAX_PACKED_LIGHTS as u32);
+        assert_eq!(globals.segment_count, MAX_PACKED_SEGMENTS as u32);
+        assert_eq!(lights.len() as u64, PackedLights::min_size().get());
+        assert_eq!(segments.len() as u64, PackedSegments::min_size().get());
+
+        let (_, _, globals) = pack_uniform_lighting_buffers(&[], &[segment; 3]);
+        assert_eq!((globals.light_count, globals.segment_count), (0, 3));
+    }
+}
@@ -1,343 +1,658 @@
-// //! This example shows how to create a custom render pass that runs after the main pass
-// //! and reads the texture generated by the main pass.
-// //!
-// //! The example shader is a very simple implementation of chromatic aberration.
-// //!
-// //! This is a fairly low level example and assumes some familiarity with rendering concepts and wgpu.
-
-// use bevy::{
-//     core_pipeline::{
-//         clear_color::ClearColorConfig, core_2d,
-//         fullscreen_vertex_shader::fullscreen_shader_vertex_state,
-//     },
-//     prelude::*,
-//     render::{
-//         extract_component::{
-//             ComponentUniforms, ExtractComponent, ExtractComponentPlugin, UniformComponentPlugin,
-//         },
-//         render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext, SlotInfo, SlotType},
-//         render_resource::{
-//             BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
-//             BindGroupLayoutEntry, BindingResource, BindingType, CachedRenderPipelineId,
-//             ColorTargetState, ColorWrites, FragmentState, MultisampleState, Operations,
-//             PipelineCache, PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor,
-//             RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages,
-//             ShaderType, TextureFormat, TextureSampleType, TextureViewDimension,
-//         },
-//         renderer::{RenderContext, RenderDevice, RenderQueue},
-//         texture::BevyDefault,
-//         view::{ExtractedView, ViewTarget},
-//         RenderApp,
-//         RenderSet, extract_resource::{ExtractResourcePlugin, ExtractResource}
-//     },
-// };
-
-// use super::LightSource;
-
-// #[derive(Resource, Default, Clone, ExtractResource)]
-// pub struct is_activeLightSources {
-//     pub lights: Vec<LightSource>,
-// }
-
-// pub 
-
-// /// It is generally encouraged to set up post processing effects as a plugin
-// pub struct LightingPlugin;
-// impl Plugin for LightingPlugin {
-//     fn build(&self, app: &mut App) {
-//         app
-//             .init_resource::<is_activeLightSources>()
-//             .add_plugin(ExtractResourcePlugin::<is_activeLightSources>::default());
-
-
-//         // We need to get the render app from the main app
-//         let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
-//             return;
-//         };
-
-//         render_app.add_system(prepare_lights.in_set(RenderSet::Prepare));
-
-//         // Initialize the pipeline
-//         render_app.init_resource::<PostProcessPipeline>();
-
-//         // Bevy's renderer uses a render graph which is a collection of nodes in a directed acyclic graph.
-//         // It currently runs on each view/camera and executes each node in the specified order.
-//         // It will make sure that any node that needs a dependency from another node only runs when that dependency is done.
-//         //
-//         // Each node can execute arbitrary work, but it generally runs at least one render pass.
-//         // A node only has access to the render world, so if you need data from the main world
-//         // you need to extract it manually or with the plugin like above.
-
-//         // Create the node with the render world
-//         let node = LightingNode::new(&mut render_app.world);
-
-//         // Get the render graph for the entire app
-//         let mut graph = render_app.world.resource_mut::<RenderGraph>();
-
-//         // Get the render graph for 3d cameras/views
-//         let core_2d_graph = graph.get_sub_graph_mut(core_2d::graph::NAME).unwrap();
-
-//         // Register the post process node in the 3d render graph
-//         core_2d_graph.add_node(LightingNode::NAME, node);
-
-//         // A slot edge tells the render graph which input/output value should be passed to the node.
-//         // In this case, the view entity, which is the entity associated with the
-//         // camera on which the graph is running.
-//         core_2d_graph.add_slot_edge(
-//             core_2d_graph.input_node().id,
-//             core_2d::graph::input::VIEW_ENTITY,
-//             LightingNode::NAME,
-//             LightingNode::IN_VIEW,
-//         );
-
-//         // We now need to add an edge between our node and the nodes from bevy
-//         // to make sure our node is ordered correctly relative to other nodes.
-//         //
-//         // Here we want our effect to run after tonemapping and before the end of the main pass post processing
-//         core_2d_graph.add_node_edge(core_2d::graph::node::TONEMAPPING, LightingNode::NAME);
-//         core_2d_graph.add_node_edge(
-//             LightingNode::NAME,
-//             core_2d::graph::node::END_MAIN_PASS_POST_PROCESSING,
-//         );
-//     }
-// }
-
-// fn prepare_lights(
-//     render_queue: Res<RenderQueue>,
-//     is_active_light_sources: Res<is_activeLightSources>,
-//     asset: Res<RenderAsset<>>
-
-// ) {
-
-// }
-
-// /// The post process node used for the render graph
-// struct LightingNode {
-//     // The node needs a query to gather data from the ECS in order to do its rendering,
-//     // but it's not a normal system so we need to define it manually.
-//     query: QueryState<&'static ViewTarget, With<ExtractedView>>,
-// }
-
-// impl LightingNode {
-//     pub const IN_VIEW: &str = "view";
-//     pub const NAME: &str = "lighting";
-
-//     fn new(world: &mut World) -> Self {
-//         Self {
-//             query: QueryState::new(world),
-//         }
-//     }
-// }
-
-// impl Node for LightingNode {
-//     // This defines the input slot of the node and tells the render graph what
-//     // we will need when running the node.
-//     fn input(&self) -> Vec<SlotInfo> {
-//         // In this case we tell the graph that our node will use the view entity.
-//         // Currently, every node in bevy uses this pattern, so it's safe to just copy it.
-//         vec![SlotInfo::new(LightingNode::IN_VIEW, SlotType::Entity)]
-//     }
-
-//     // This will run every frame before the run() method
-//     // The important difference is that `self` is `mut` here
-//     fn update(&mut self, world: &mut World) {
-//         // Since this is not a system we need to update the query manually.
-//         // This is mostly boilerplate. There are plans to remove this in the future.
-//         // For now, you can just copy it.
-//         self.query.update_archetypes(world);
-//     }
-
-//     // Runs the node logic
-//     // This is where you encode draw commands.
-//     //
-//     // This will run on every view on which the graph is running. If you don't want your effect to run on every camera,
-//     // you'll need to make sure you have a marker component to identify which camera(s) should run the effect.
-//     fn run(
-//         &self,
-//         graph_context: &mut RenderGraphContext,
-//         render_context: &mut RenderContext,
-//         world: &World,
-//     ) -> Result<(), NodeRunError> {
-//         // Get the entity of the view for the render graph where this node is running
-//         let view_entity = graph_context.get_input_entity(LightingNode::IN_VIEW)?;
-
-//         // We get the data we need from the world based on the view entity passed to the node.
-//         // The data is the query that was defined earlier in the [`LightingNode`]
-//         let Ok(view_target) = self.query.get_manual(world, view_entity) else {
-//             return Ok(());
-//         };
-
-//         // Get the pipeline resource that contains the global data we need to create the render pipeline
-//         let lighting_pipeline = world.resource::<PostProcessPipeline>();
-
-//         // The pipeline cache is a cache of all previously created pipelines.
-//         // It is required to avoid creating a new pipeline each frame, which is expensive due to shader compilation.
-//         let pipeline_cache = world.resource::<PipelineCache>();
-
-//         // Get the pipeline from the cache
-//         let Some(pipeline) = pipeline_cache.get_render_pipeline(lighting_pipeline.pipeline_id) else {
-//             return Ok(());
-//         };
-
-//         let light_uniform = world.resource::<ComponentUniforms<LightSource>>();
-
-//         let Some(light_binding) = light_uniform.uniforms().binding() else {
-//             return Ok(());
-//         };
-
-//         // This will start a new "post process write", obtaining two texture
-//         // views from the view target - a `source` and a `destination`.
-//         // `source` is the "current" main texture and you _must_ write into
-//         // `destination` because calling `post_process_write()` on the
-//         // [`ViewTarget`] will internally flip the [`ViewTarget`]'s main
-//         // texture to the `destination` texture. Failing to do so will cause
-//         // the current main texture information to be lost.
-//         let lighting = view_target.post_process_write();
-
-//         // The bind_group gets created each frame.
-//         //
-//         // Normally, you would create a bind_group in the Queue stage, but this doesn't work with the post_process_write().
-//         // The reason it doesn't work is because each post_process_write will alternate the source/destination.
-//         // The only way to have the correct source/destination for the bind_group is to make sure you get it during the node execution.
-//         let bind_group = render_context
-//             .render_device()
-//             .create_bind_group(&BindGroupDescriptor {
-//                 label: Some("lighting_bind_group"),
-//                 layout: &lighting_pipeline.layout,
-//                 // It's important for this to match the BindGroupLayout defined in the PostProcessPipeline
-//                 entries: &[
-//                     BindGroupEntry {
-//                         binding: 0,
-//                         // Make sure to use the source view
-//                         resource: BindingResource::TextureView(lighting.source),
-//                     },
-//                     BindGroupEntry {
-//                         binding: 1,
-//                         // Use the sampler created for the pipeline
-//                         resource: BindingResource::Sampler(&lighting_pipeline.sampler),
-//                     },
-//                     BindGroupEntry {
-//                         binding: 2,
-//                         // Use the sampler created for the pipeline
-//                         resource: light_binding.clone(),
-//                     },
-                                
-//                 ],
-//             });
-
-//         // Begin the render pass
-//         let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-//             label: Some("lighting_pass"),
-//             color_attachments: &[Some(RenderPassColorAttachment {
-//                 // We need to specify the post process destination view here
-//                 // to make sure we write to the appropriate texture.
-//                 view: lighting.destination,
-//                 resolve_target: None,
-//                 ops: Operations::default(),
-//             })],
-//             depth_stencil_attachment: None,
-//         });
-
-//         // This is mostly just wgpu boilerplate for drawing a fullscreen triangle,
-//         // using the pipeline/bind_group created above
-//         render_pass.set_render_pipeline(pipeline);
-//         render_pass.set_bind_group(0, &bind_group, &[]);
-//         render_pass.draw(0..3, 0..1);
-
-//         Ok(())
-//     }
-// }
-
-// // This contains global data used by the render pipeline. This will be created once on startup.
-// #[derive(Resource)]
-// struct PostProcessPipeline {
-//     layout: BindGroupLayout,
-//     sampler: Sampler,
-//     pipeline_id: CachedRenderPipelineId,
-// }
-
-// impl FromWorld for PostProcessPipeline {
-//     fn from_world(world: &mut World) -> Self {
-//         let render_device = world.resource::<RenderDevice>();
-
-//         // We need to define the bind group layout used for our pipeline
-//         let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-//             label: Some("lighting_bind_group_layout"),
-//             entries: &[
-//                 // The screen texture
-//                 BindGroupLayoutEntry {
-//                     binding: 0,
-//                     visibility: ShaderStages::FRAGMENT,
-//                     ty: BindingType::Texture {
-//                         sample_type: TextureSampleType::Float { filterable: true },
-//                         view_dimension: TextureViewDimension::D2,
-//                         multisampled: false,
-//                     },
-//                     count: None,
-//                 },
-//                 // The sampler that will be used to sample the screen texture
-//                 BindGroupLayoutEntry {
-//                     binding: 1,
-//                     visibility: ShaderStages::FRAGMENT,
-//                     ty: BindingType::Sampler(SamplerBindingType::Filtering),
-//                     count: None,
-//                 },
-
-//                 BindGroupLayoutEntry {
-//                     binding: 2,
-//                     visibility: ShaderStages::FRAGMENT,
-//                     ty: BindingType::Buffer {
-//                         ty: bevy::render::render_resource::BufferBindingType::Uniform,
-//                         has_dynamic_offset: false,
-//                         min_binding_size: None,
-//                     },
-//                     count: None,
-//                 },
-//             ],
-//         });
-
-//         // We can create the sampler here since it won't change at runtime and doesn't depend on the view
-//         let sampler = render_device.create_sampler(&SamplerDescriptor::default());
-
-//         // Get the shader handle
-//         let shader = world
-//             .resource::<AssetServer>()
-//             .load("shaders/lighting_pass.wgsl");
-
-//         let pipeline_id = world
-//             .resource_mut::<PipelineCache>()
-//             // This will add the pipeline to the cache and queue it's creation
-//             .queue_render_pipeline(RenderPipelineDescriptor {
-//                 label: Some("lighting_pipeline".into()),
-//                 layout: vec![layout.clone()],
-//                 // This will setup a fullscreen triangle for the vertex state
-//                 vertex: fullscreen_shader_vertex_state(),
-//                 fragment: Some(FragmentState {
-//                     shader,
-//                     shader_defs: vec![],
-//                     // Make sure this matches the entry point of your shader.
-//                     // It can be anything as long as it matches here and in the shader.
-//                     entry_point: "fragment".into(),
-//                     targets: vec![Some(ColorTargetState {
-//                         format: TextureFormat::bevy_default(),
-//                         blend: None,
-//                         write_mask: ColorWrites::ALL,
-//                     })],
-//                 }),
-//                 // All of the following property are not important for this effect so just use the default values.
-//                 // This struct doesn't have the Default trai implemented because not all field can have a default value.
-//                 primitive: PrimitiveState::default(),
-//                 depth_stencil: None,
-//                 multisample: MultisampleState::default(),
-//                 push_constant_ranges: vec![],
-//             });
-
-//         Self {
-//             layout,
-//             sampler,
-//             pipeline_id,
-//         }
-//     }
-// }
-
-
+//! The lighting pass: a full-screen node in the core_2d render graph that lights everything the camera sees, after
+//! the main pass and before bloom and tonemapping. The light is rendered into the light texture of the view first,
+//! at the resolution of [`LightingQuality`], and then multiplied over the view.
+
+use bevy::{
+    core_pipeline::{core_2d, fullscreen_vertex_shader::fullscreen_shader_vertex_state},
+    prelude::*,
+    render::{
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssets,
+        render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext, SlotInfo, SlotType},
+        render_resource::{
+            encase, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+            BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType, BufferDescriptor,
+            BufferUsages, CachedRenderPipelineId, ColorTargetState, ColorWrites, Extent3d, FragmentState,
+            MultisampleState, Operations, PipelineCache, PrimitiveState, RenderPassColorAttachment,
+            RenderPassDescriptor, RenderPipelineDescriptor, FilterMode, Sampler, SamplerBindingType, SamplerDescriptor,
+            ShaderType, ShaderStages, SpecializedRenderPipeline, SpecializedRenderPipelines, TextureDescriptor,
+            TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureViewDimension,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::{BevyDefault, CachedTexture, FallbackImage, GpuImage, TextureCache},
+        view::{ExtractedView, ViewTarget, ViewUniform, ViewUniformOffset, ViewUniforms},
+        Extract, RenderApp, RenderSet,
+    },
+};
+
+use super::{
+    pack_lighting_buffers, BakedLighting, GlobalIllumination, GpuLightSource, LightModulation, LightSource,
+    LightingGlobals, LightingQuality, OccluderDistanceField, OccluderGrid,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use super::pack_grid_buffers;
+
+/// Marks the cameras whose view gets lit
+#[derive(Component, Clone, Copy, Default, ExtractComponent)]
+pub struct LightingCamera;
+
+/// The textures the lighting pass samples besides the screen. The normal and emissive maps cover the map image,
+/// which is centered on the world origin with one pixel per world unit. The baked lightmap and the distance field
+/// come from [`BakedLighting`] and [`OccluderDistanceField`].
+#[derive(Resource, ExtractResource, Clone, Default)]
+pub struct LightingTextures {
+    /// Only its size matters to the lighting pass, the map itself is drawn by the background sprite
+    pub map: Handle<Image>,
+    /// One row per curve of [`super::FalloffCurves`], kept up to date by `update_falloff_curve_texture`
+    pub falloff_curves: Handle<Image>,
+    /// Tangent space normals of the map with y pointing up, has to be loaded as linear and not sRGB.
+    /// Without one everything is lit as if it was flat.
+    pub normal_map: Option<Handle<Image>>,
+    /// Glowing parts of the map, added on top of the lit map. Black pixels don't glow.
+    pub emissive_map: Option<Handle<Image>>,
+}
+
+pub struct LightingPlugin;
+
+/// Renders the lights of the level on top of whatever the main pass drew for every [`LightingCamera`], so the map,
+/// the walls and every other sprite and mesh are lit wherever the camera pans and zooms to. The UI is drawn after
+/// this and stays unlit.
+impl Plugin for LightingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LightingTextures>()
+            .add_plugin(ExtractComponentPlugin::<LightingCamera>::default())
+            .add_plugin(ExtractResourcePlugin::<LightingTextures>::default());
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<LightingPipeline>()
+            .init_resource::<SpecializedRenderPipelines<LightingPipeline>>()
+            .init_resource::<LightingBuffers>()
+            .add_system(extract_lights.in_schedule(ExtractSchedule).in_set(RenderSet::ExtractCommands))
+            .add_system(prepare_lighting_buffers.in_set(RenderSet::Prepare))
+            .add_system(prepare_lighting_pipelines.in_set(RenderSet::Prepare))
+            .add_system(prepare_light_textures.in_set(RenderSet::Prepare))
+            .add_system(queue_lighting_bind_group.in_set(RenderSet::Queue));
+
+        let node = LightingNode::new(&mut render_app.world);
+        let mut graph = render_app.world.resource_mut::<RenderGraph>();
+        let core_2d_graph = graph.get_sub_graph_mut(core_2d::graph::NAME).unwrap();
+        core_2d_graph.add_node(LightingNode::NAME, node);
+        core_2d_graph.add_slot_edge(
+            core_2d_graph.input_node().id,
+            core_2d::graph::input::VIEW_ENTITY,
+            LightingNode::NAME,
+            LightingNode::IN_VIEW,
+        );
+        // MAIN_PASS -> LIGHTING -> BLOOM, so bright lights bloom
+        core_2d_graph.add_node_edge(core_2d::graph::node::MAIN_PASS, LightingNode::NAME);
+        core_2d_graph.add_node_edge(LightingNode::NAME, core_2d::graph::node::BLOOM);
+    }
+}
+
+// Animated lights are extracted as they look right now, see `LightModulation`
+fn extract_lights(
+    mut commands: Commands,
+    light_q: Extract<Query<(Entity, &LightSource, &GlobalTransform, Option<&LightModulation>)>>,
+) {
+    for (entity, light_source, global_trans, modulation) in &light_q {
+        let light_source = modulation.map_or(*light_source, |modulation| modulation.apply(light_source));
+        commands.get_or_spawn(entity).insert(light_source).insert(*global_trans);
+    }
+}
+
+/// The buffers of the lighting pass, rewritten every frame by `prepare_lighting_buffers`. Lights and segments are
+/// storage buffers, or uniform buffers on WebGL2.
+#[derive(Resource, Default)]
+struct LightingBuffers {
+    lights: Option<Buffer>,
+    segments: Option<Buffer>,
+    grid_cells: Option<Buffer>,
+    grid_indices: Option<Buffer>,
+    globals: Option<Buffer>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const ARRAY_BUFFER_USAGE: BufferUsages = BufferUsages::STORAGE;
+#[cfg(target_arch = "wasm32")]
+const ARRAY_BUFFER_USAGE: BufferUsages = BufferUsages::UNIFORM;
+
+fn prepare_lighting_buffers(
+    light_sources: Query<(Entity, &LightSource, &GlobalTransform)>,
+    occluder_grid: Res<OccluderGrid>,
+    distance_field: Res<OccluderDistanceField>,
+    illumination: Res<GlobalIllumination>,
+    baked: Res<BakedLighting>,
+    textures: Res<LightingTextures>,
+    images: Res<RenderAssets<Image>>,
+    mut buffers: ResMut<LightingBuffers>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    // Baked lights are already in the lightmap
+    let lights: Vec<GpuLightSource> = light_sources
+        .iter()
+        .filter(|(entity, ..)| !(baked.lightmap.is_some() && baked.lights.contains(entity)))
+        .map(|(_, light_source, light_global_trans)| GpuLightSource::new(light_source, light_global_trans))
+        .collect();
+
+    // The segments come sorted into the grid, which is only rebuilt when an occluder changes
+    let (lights_bytes, segments_bytes, mut globals) = pack_lighting_buffers(&lights, &occluder_grid.segments);
+    globals.grid_origin = occluder_grid.origin;
+    globals.grid_cell_size = occluder_grid.cell_size;
+    globals.grid_size = occluder_grid.size;
+    globals.field_origin = distance_field.origin;
+    globals.field_texel_size = distance_field.texel_size;
+    globals.ambient = illumination.ambient();
+    globals.darkness = illumination.darkness;
+    globals.emissive_spread = illumination.emissive_spread;
+    globals.emissive_light = illumination.emissive_light;
+    globals.map_size = images.get(&textures.map).map_or(Vec2::ONE, |map| map.size);
+
+    let mut globals_buffer = encase::UniformBuffer::new(Vec::new());
+    globals_buffer.write(&globals).unwrap();
+
+    let buffers = buffers.as_mut();
+    write_buffer(&mut buffers.lights, ARRAY_BUFFER_USAGE, &lights_bytes, &render_device, &render_queue);
+    write_buffer(&mut buffers.segments, ARRAY_BUFFER_USAGE, &segments_bytes, &render_device, &render_queue);
+    write_buffer(&mut buffers.globals, BufferUsages::UNIFORM, globals_buffer.as_ref(), &render_device, &render_queue);
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let (cells_bytes, indices_bytes) = pack_grid_buffers(&occluder_grid);
+        write_buffer(&mut buffers.grid_cells, BufferUsages::STORAGE, &cells_bytes, &render_device, &render_queue);
+        write_buffer(&mut buffers.grid_indices, BufferUsages::STORAGE, &indices_bytes, &render_device, &render_queue);
+    }
+}
+
+// Writes `data` into the buffer. If the buffer is too small it gets replaced by a bigger one.
+fn write_buffer(
+    buffer: &mut Option<Buffer>,
+    usage: BufferUsages,
+    data: &[u8],
+    render_device: &RenderDevice,
+    render_queue: &RenderQueue,
+) {
+    if let Some(buffer) = buffer.as_ref().filter(|buffer| buffer.size() as usize >= data.len()) {
+        render_queue.write_buffer(buffer, 0, data);
+        return;
+    }
+
+    let grown = render_device.create_buffer(&BufferDescriptor {
+        label: Some("lighting_buffer"),
+        size: data.len().next_power_of_two() as u64,
+        usage: usage | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    render_queue.write_buffer(&grown, 0, data);
+    *buffer = Some(grown);
+}
+
+// The optional textures that are loaded, the shader only samples those
+struct LoadedTextures<'a> {
+    falloff_curves: Option<&'a GpuImage>,
+    normal_map: Option<&'a GpuImage>,
+    emissive_map: Option<&'a GpuImage>,
+    baked_lightmap: Option<&'a GpuImage>,
+    distance_field: Option<&'a GpuImage>,
+}
+
+impl<'a> LoadedTextures<'a> {
+    fn new(
+        textures: &LightingTextures,
+        baked: &BakedLighting,
+        distance_field: &OccluderDistanceField,
+        images: &'a RenderAssets<Image>,
+    ) -> Self {
+        let get = |handle: Option<&Handle<Image>>| handle.and_then(|handle| images.get(handle));
+        Self {
+            falloff_curves: get(Some(&textures.falloff_curves)),
+            normal_map: get(textures.normal_map.as_ref()),
+            emissive_map: get(textures.emissive_map.as_ref()),
+            baked_lightmap: get(baked.lightmap.as_ref()),
+            distance_field: get(distance_field.texture.as_ref()),
+        }
+    }
+}
+
+/// The lighting pipelines of a view, one for each half of the pass
+#[derive(Component)]
+struct ViewLightingPipelines {
+    accumulate: CachedRenderPipelineId,
+    composite: CachedRenderPipelineId,
+}
+
+fn prepare_lighting_pipelines(
+    mut commands: Commands,
+    pipeline_cache: Res<PipelineCache>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<LightingPipeline>>,
+    lighting_pipeline: Res<LightingPipeline>,
+    textures: Res<LightingTextures>,
+    baked: Res<BakedLighting>,
+    distance_field: Res<OccluderDistanceField>,
+    images: Res<RenderAssets<Image>>,
+    view_q: Query<(Entity, &ExtractedView), With<LightingCamera>>,
+) {
+    let loaded = LoadedTextures::new(&textures, &baked, &distance_field, &images);
+    for (entity, view) in view_q.iter() {
+        let key = LightingPipelineKey {
+            pass: LightingPass::Accumulate,
+            normal_map: loaded.normal_map.is_some(),
+            emissive_map: loaded.emissive_map.is_some(),
+            baked_lightmap: loaded.baked_lightmap.is_some(),
+            distance_field: loaded.distance_field.is_some(),
+            hdr: view.hdr,
+        };
+        commands.entity(entity).insert(ViewLightingPipelines {
+            accumulate: pipelines.specialize(&pipeline_cache, &lighting_pipeline, key),
+            composite: pipelines.specialize(
+                &pipeline_cache,
+                &lighting_pipeline,
+                LightingPipelineKey {
+                    pass: LightingPass::Composite,
+                    ..key
+                },
+            ),
+        });
+    }
+}
+
+/// The light falling onto a view, rendered by the first half of the pass. Every view has its own, so views of
+/// different sizes, e.g. a second window or a camera rendering to a texture, don't overwrite each other's light.
+#[derive(Component)]
+struct ViewLightTexture(CachedTexture);
+
+/// Floats, so the light can add up to more than 1
+const LIGHT_TEXTURE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+// The light texture has the size of the viewport, scaled down by the quality setting. The texture cache hands out
+// the same textures again as long as the sizes stay the same.
+fn prepare_light_textures(
+    mut commands: Commands,
+    quality: Res<LightingQuality>,
+    mut texture_cache: ResMut<TextureCache>,
+    render_device: Res<RenderDevice>,
+    view_q: Query<(Entity, &ExtractedView), With<LightingCamera>>,
+) {
+    let divisor = quality.resolution.divisor();
+    for (entity, view) in view_q.iter() {
+        let size = (UVec2::new(view.viewport.z, view.viewport.w) / divisor).max(UVec2::ONE);
+        let texture = texture_cache.get(
+            &render_device,
+            TextureDescriptor {
+                label: Some("light_texture"),
+                size: Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    ..default()
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: LIGHT_TEXTURE_FORMAT,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            },
+        );
+        commands.entity(entity).insert(ViewLightTexture(texture));
+    }
+}
+
+/// Everything but the screen and the view, shared by all views
+#[derive(Resource)]
+struct LightingBindGroup(BindGroup);
+
+// Textures that aren't there yet are replaced by the fallback image, the shader doesn't sample them
+fn queue_lighting_bind_group(
+    mut commands: Commands,
+    lighting_pipeline: Res<LightingPipeline>,
+    buffers: Res<LightingBuffers>,
+    textures: Res<LightingTextures>,
+    baked: Res<BakedLighting>,
+    distance_field: Res<OccluderDistanceField>,
+    images: Res<RenderAssets<Image>>,
+    fallback_image: Res<FallbackImage>,
+    render_device: Res<RenderDevice>,
+) {
+    let (Some(lights), Some(segments), Some(globals)) = (&buffers.lights, &buffers.segments, &buffers.globals) else {
+        return;
+    };
+
+    let loaded = LoadedTextures::new(&textures, &baked, &distance_field, &images);
+    let fallback_image: &GpuImage = &fallback_image;
+    let image = |image: Option<_>| image.unwrap_or(fallback_image);
+    let (falloff_curves, normal_map, emissive_map) =
+        (image(loaded.falloff_curves), image(loaded.normal_map), image(loaded.emissive_map));
+    let (baked_lightmap, distance_field) = (image(loaded.baked_lightmap), image(loaded.distance_field));
+
+    let mut entries = vec![
+        BindGroupEntry { binding: 2, resource: lights.as_entire_binding() },
+        BindGroupEntry { binding: 3, resource: segments.as_entire_binding() },
+        BindGroupEntry { binding: 4, resource: globals.as_entire_binding() },
+        BindGroupEntry { binding: 5, resource: BindingResource::TextureView(&falloff_curves.texture_view) },
+        BindGroupEntry { binding: 6, resource: BindingResource::TextureView(&normal_map.texture_view) },
+        BindGroupEntry { binding: 7, resource: BindingResource::Sampler(&normal_map.sampler) },
+        BindGroupEntry { binding: 8, resource: BindingResource::TextureView(&emissive_map.texture_view) },
+        BindGroupEntry { binding: 9, resource: BindingResource::Sampler(&emissive_map.sampler) },
+        BindGroupEntry { binding: 10, resource: BindingResource::TextureView(&baked_lightmap.texture_view) },
+        BindGroupEntry { binding: 11, resource: BindingResource::Sampler(&baked_lightmap.sampler) },
+        BindGroupEntry { binding: 14, resource: BindingResource::TextureView(&distance_field.texture_view) },
+    ];
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let (Some(grid_cells), Some(grid_indices)) = (&buffers.grid_cells, &buffers.grid_indices) else {
+            return;
+        };
+        entries.push(BindGroupEntry { binding: 12, resource: grid_cells.as_entire_binding() });
+        entries.push(BindGroupEntry { binding: 13, resource: grid_indices.as_entire_binding() });
+    }
+
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: Some("lighting_bind_group"),
+        layout: &lighting_pipeline.lighting_layout,
+        entries: &entries,
+    });
+    commands.insert_resource(LightingBindGroup(bind_group));
+}
+
+/// The two halves of the lighting pass
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum LightingPass {
+    /// Renders the light into the light texture
+    Accumulate,
+    /// Multiplies the light texture over the view
+    Composite,
+}
+
+/// Selects the shader variant, with or without normal, emissive and baked lightmaps, with segment or distance field
+/// shadows and for LDR or HDR views
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct LightingPipelineKey {
+    pass: LightingPass,
+    normal_map: bool,
+    emissive_map: bool,
+    baked_lightmap: bool,
+    distance_field: bool,
+    hdr: bool,
+}
+
+#[derive(Resource)]
+struct LightingPipeline {
+    /// Only the view uniform, the light texture is the render target
+    accumulate_layout: BindGroupLayout,
+    /// The screen texture, its sampler, the view uniform and the light texture with its sampler
+    composite_layout: BindGroupLayout,
+    /// Lights, occluders and the optional textures, the binding indices are the ones in the shader
+    lighting_layout: BindGroupLayout,
+    screen_sampler: Sampler,
+    /// Bilinear, so a smaller light texture gets smoothly upsampled
+    light_sampler: Sampler,
+    shader: Handle<Shader>,
+}
+
+impl FromWorld for LightingPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let entry = |binding: u32, ty: BindingType| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty,
+            count: None,
+        };
+        let buffer = |ty: BufferBindingType| BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        };
+        let texture = |filterable: bool| BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        };
+        let sampler = BindingType::Sampler(SamplerBindingType::Filtering);
+        let view_uniform = BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: true,
+            min_binding_size: Some(ViewUniform::min_size()),
+        };
+
+        let accumulate_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("lighting_accumulate_bind_group_layout"),
+            entries: &[entry(2, view_uniform)],
+        });
+
+        let composite_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("lighting_composite_bind_group_layout"),
+            entries: &[
+                entry(0, texture(true)),
+                entry(1, sampler),
+                entry(2, view_uniform),
+                entry(3, texture(true)),
+                entry(4, sampler),
+            ],
+        });
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let array_buffer = buffer(BufferBindingType::Storage { read_only: true });
+        #[cfg(target_arch = "wasm32")]
+        let array_buffer = buffer(BufferBindingType::Uniform);
+
+        let mut lighting_entries = vec![
+            entry(2, array_buffer),
+            entry(3, array_buffer),
+            entry(4, BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: Some(LightingGlobals::min_size()),
+            }),
+            entry(5, texture(false)),
+            entry(6, texture(true)),
+            entry(7, sampler),
+            entry(8, texture(true)),
+            entry(9, sampler),
+            entry(10, texture(true)),
+            entry(11, sampler),
+            entry(14, texture(false)),
+        ];
+        #[cfg(not(target_arch = "wasm32"))]
+        lighting_entries.extend([entry(12, array_buffer), entry(13, array_buffer)]);
+
+        let lighting_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("lighting_bind_group_layout"),
+            entries: &lighting_entries,
+        });
+
+        Self {
+            accumulate_layout,
+            composite_layout,
+            lighting_layout,
+            screen_sampler: render_device.create_sampler(&SamplerDescriptor::default()),
+            light_sampler: render_device.create_sampler(&SamplerDescriptor {
+                mag_filter: FilterMode::Linear,
+                min_filter: FilterMode::Linear,
+                ..default()
+            }),
+            shader: world.resource::<AssetServer>().load("shaders/lighting_pass.wgsl"),
+        }
+    }
+}
+
+impl SpecializedRenderPipeline for LightingPipeline {
+    type Key = LightingPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let mut shader_defs = Vec::new();
+        if cfg!(target_arch = "wasm32") {
+            shader_defs.push("NO_STORAGE_BUFFERS".into());
+        }
+        if key.normal_map {
+            shader_defs.push("NORMAL_MAP".into());
+        }
+        if key.emissive_map {
+            shader_defs.push("EMISSIVE_MAP".into());
+        }
+        if key.baked_lightmap {
+            shader_defs.push("BAKED_LIGHTMAP".into());
+        }
+        if key.distance_field {
+            shader_defs.push("DISTANCE_FIELD".into());
+        }
+
+        let (label, layout, entry_point, format) = match key.pass {
+            LightingPass::Accumulate => (
+                "lighting_accumulate_pipeline",
+                &self.accumulate_layout,
+                "accumulate",
+                LIGHT_TEXTURE_FORMAT,
+            ),
+            LightingPass::Composite => (
+                "lighting_composite_pipeline",
+                &self.composite_layout,
+                "fragment",
+                if key.hdr { ViewTarget::TEXTURE_FORMAT_HDR } else { TextureFormat::bevy_default() },
+            ),
+        };
+
+        RenderPipelineDescriptor {
+            label: Some(label.into()),
+            layout: vec![layout.clone(), self.lighting_layout.clone()],
+            vertex: fullscreen_shader_vertex_state(),
+            fragment: Some(FragmentState {
+                shader: self.shader.clone(),
+                shader_defs,
+                entry_point: entry_point.into(),
+                targets: vec![Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            push_constant_ranges: Vec::new(),
+        }
+    }
+}
+
+struct LightingNode {
+    query: QueryState<
+        (
+            &'static ViewTarget,
+            &'static ViewUniformOffset,
+            &'static ViewLightingPipelines,
+            &'static ViewLightTexture,
+        ),
+        With<ExtractedView>,
+    >,
+}
+
+impl LightingNode {
+    pub const IN_VIEW: &str = "view";
+    pub const NAME: &str = "lighting";
+
+    fn new(world: &mut World) -> Self {
+        Self {
+            query: QueryState::new(world),
+        }
+    }
+}
+
+impl Node for LightingNode {
+    fn input(&self) -> Vec<SlotInfo> {
+        vec![SlotInfo::new(LightingNode::IN_VIEW, SlotType::Entity)]
+    }
+
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph_context: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let view_entity = graph_context.get_input_entity(LightingNode::IN_VIEW)?;
+        let Ok((view_target, view_uniform_offset, view_pipelines, ViewLightTexture(light_texture))) =
+            self.query.get_manual(world, view_entity)
+        else {
+            return Ok(());
+        };
+
+        let lighting_pipeline = world.resource::<LightingPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let (Some(accumulate_pipeline), Some(composite_pipeline)) = (
+            pipeline_cache.get_render_pipeline(view_pipelines.accumulate),
+            pipeline_cache.get_render_pipeline(view_pipelines.composite),
+        ) else {
+            return Ok(());
+        };
+        let Some(LightingBindGroup(lighting_bind_group)) = world.get_resource::<LightingBindGroup>() else {
+            return Ok(());
+        };
+        let Some(view_binding) = world.resource::<ViewUniforms>().uniforms.binding() else {
+            return Ok(());
+        };
+
+        let accumulate_bind_group = render_context.render_device().create_bind_group(&BindGroupDescriptor {
+            label: Some("lighting_accumulate_bind_group"),
+            layout: &lighting_pipeline.accumulate_layout,
+            entries: &[BindGroupEntry { binding: 2, resource: view_binding.clone() }],
+        });
+
+        {
+            let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("lighting_accumulate_pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &light_texture.default_view,
+                    resolve_target: None,
+                    ops: Operations::default(),
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            // A single triangle covering the whole light texture
+            render_pass.set_render_pipeline(accumulate_pipeline);
+            render_pass.set_bind_group(0, &accumulate_bind_group, &[view_uniform_offset.offset]);
+            render_pass.set_bind_group(1, lighting_bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        // Reads the main texture and writes the other one, which becomes the main texture. The source changes
+        // every time, so this bind group can't be made ahead of time.
+        let post_process = view_target.post_process_write();
+        let composite_bind_group = render_context.render_device().create_bind_group(&BindGroupDescriptor {
+            label: Some("lighting_composite_bind_group"),
+            layout: &lighting_pipeline.composite_layout,
+            entries: &[
+                BindGroupEntry { binding: 0, resource: BindingResource::TextureView(post_process.source) },
+                BindGroupEntry { binding: 1, resource: BindingResource::Sampler(&lighting_pipeline.screen_sampler) },
+                BindGroupEntry { binding: 2, resource: view_binding },
+                BindGroupEntry { binding: 3, resource: BindingResource::TextureView(&light_texture.default_view) },
+                BindGroupEntry { binding: 4, resource: BindingResource::Sampler(&lighting_pipeline.light_sampler) },
+            ],
+        });
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("lighting_composite_pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: post_process.destination,
+                resolve_target: None,
+                ops: Operations::default(),
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_render_pipeline(composite_pipeline);
+        render_pass.set_bind_group(0, &composite_bind_group, &[view_uniform_offset.offset]);
+        render_pass.set_bind_group(1, lighting_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
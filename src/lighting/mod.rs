@@ -1,10 +1,25 @@
-// mod lighting_plugin;
-// mod post_process_example;
+mod lighting_plugin;
 mod lighting_material_plugin;
 mod components;
+mod cpu_lighting;
+mod falloff;
+mod global_illumination;
+mod bake;
+mod light_animation;
+mod occluder_grid;
+mod distance_field;
+mod occluder_tracing;
+mod hdr;
 
-// pub use lighting_plugin::LightingPlugin;
-// pub use post_process_example::PostProcessPlugin;
-// pub use post_process_example::PostProcessSettings;
+pub use lighting_plugin::*;
 pub use components::*;
-pub use lighting_material_plugin::*;
\ No newline at end of file
+pub use lighting_material_plugin::*;
+pub use cpu_lighting::*;
+pub use falloff::*;
+pub use global_illumination::*;
+pub use bake::*;
+pub use light_animation::*;
+pub use occluder_grid::*;
+pub use distance_field::*;
+pub use occluder_tracing::*;
+pub use hdr::*;
\ No newline at end of file
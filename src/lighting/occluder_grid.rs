@@ -0,0 +1,384 @@
+use bevy::{prelude::*, render::extract_resource::ExtractResource, render::render_resource::ShaderType};
+
+use super::{GpuSegment, LightOccluder};
+
+/// The grid never has more cells than this along either axis
+pub const MAX_GRID_SIZE: u32 = 64;
+/// Cells don't get smaller than this, in world units
+pub const MIN_GRID_CELL_SIZE: f32 = 16.0;
+// Segments are added to every cell within this distance, so rays passing exactly through a cell corner can't miss them
+const GRID_PADDING: f32 = 1.0;
+
+/// The segments of a grid cell are `indices[start..start + count]`
+#[derive(Clone, Copy, Default, ShaderType, Debug)]
+pub struct GpuGridCell {
+    pub start: u32,
+    pub count: u32,
+}
+
+/// Uniform grid over all occluder segments. Shadow rays only test the segments in the cells they pass through,
+/// instead of every segment in the level.
+///
+/// Rebuilt by `update_occluder_grid` whenever an occluder is added, changed, moved or removed, then extracted to
+/// the render world and uploaded next to the segments.
+#[derive(Resource, ExtractResource, Clone, Default, Debug)]
+pub struct OccluderGrid {
+    pub segments: Vec<GpuSegment>,
+    /// The occluder every segment belongs to
+    pub owners: Vec<Entity>,
+    /// World space corner of cell (0, 0), cells go along +x and +y from here
+    pub origin: Vec2,
+    pub cell_size: f32,
+    /// Number of cells along x and y, zero when there are no segments
+    pub size: UVec2,
+    /// Row by row, starting at `origin`
+    pub cells: Vec<GpuGridCell>,
+    /// Indices into `segments`
+    pub indices: Vec<u32>,
+}
+
+impl OccluderGrid {
+    /// Builds the grid over world space segments, each paired with the occluder it belongs to. The segments of an
+    /// occluder have to come in a row. The grid covers the bounds of all segments, with roughly one cell per segment.
+    pub fn new(segments: impl IntoIterator<Item = (Entity, GpuSegment)>) -> Self {
+        let (owners, mut segments): (Vec<Entity>, Vec<GpuSegment>) = segments.into_iter().unzip();
+        let mut first = 0;
+        for (index, segment) in segments.iter_mut().enumerate() {
+            if index > 0 && owners[index] != owners[index - 1] {
+                first = index as u32;
+            }
+            segment.first = first;
+        }
+        let Some(first) = segments.first() else {
+            return Self::default();
+        };
+
+        let bounds = segments.iter().fold(Rect::from_corners(first.a, first.a), |bounds, segment| {
+            bounds.union_point(segment.a).union_point(segment.b)
+        });
+        let extent = bounds.size() + 2.0 * GRID_PADDING;
+        let cells_along = (segments.len() as f32).sqrt().ceil().clamp(1.0, MAX_GRID_SIZE as f32);
+        let cell_size = (extent.max_element() / cells_along).max(MIN_GRID_CELL_SIZE);
+
+        let mut grid = Self {
+            origin: bounds.min - GRID_PADDING,
+            cell_size,
+            size: (extent / cell_size).ceil().as_uvec2().max(UVec2::ONE),
+            ..default()
+        };
+
+        // Count the segments per cell, then fill them in behind each other
+        let ranges: Vec<(UVec2, UVec2)> = segments
+            .iter()
+            .map(|segment| grid.cell_range(Rect::from_corners(segment.a, segment.b).inset(GRID_PADDING)))
+            .collect();
+        let mut counts = vec![0u32; (grid.size.x * grid.size.y) as usize];
+        for (min, max) in ranges.iter() {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    counts[(y * grid.size.x + x) as usize] += 1;
+                }
+            }
+        }
+
+        let mut start = 0;
+        grid.cells = counts
+            .iter()
+            .map(|count| {
+                let cell = GpuGridCell { start, count: 0 };
+                start += count;
+                cell
+            })
+            .collect();
+        grid.indices = vec![0; start as usize];
+        for (index, (min, max)) in ranges.iter().enumerate() {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let cell = &mut grid.cells[(y * grid.size.x + x) as usize];
+                    grid.indices[(cell.start + cell.count) as usize] = index as u32;
+                    cell.count += 1;
+                }
+            }
+        }
+
+        grid.segments = segments;
+        grid.owners = owners;
+        grid
+    }
+
+    /// Indices of the segments in a cell
+    pub fn cell_segments(&self, cell: usize) -> &[u32] {
+        let cell = self.cells[cell];
+        &self.indices[cell.start as usize..(cell.start + cell.count) as usize]
+    }
+
+    // First and last cell covered by `rect`, clamped to the grid
+    fn cell_range(&self, rect: Rect) -> (UVec2, UVec2) {
+        let last = self.size - 1;
+        let cell = |point: Vec2| ((point - self.origin) / self.cell_size).floor().max(Vec2::ZERO).as_uvec2().min(last);
+        (cell(rect.min), cell(rect.max))
+    }
+
+    /// Walks the cells along the line from `start` to `end` in order, until `visit` returns true. Next to the cell
+    /// `visit` gets the part of the line inside it, from 0 at `start` to 1 at `end`. The first part is open ended, and
+    /// so is the last one unless the line leaves the grid there, so every point of the line inside the grid belongs to
+    /// exactly one visited cell.
+    /// Same as the traversal in `transmittance` in the shader.
+    pub fn visit_cells(&self, start: Vec2, end: Vec2, mut visit: impl FnMut(usize, (f32, f32)) -> bool) -> bool {
+        if self.size.x == 0 {
+            return false;
+        }
+
+        // Clip the line to the grid, there are no segments outside of it
+        let grid_max = self.origin + self.size.as_vec2() * self.cell_size;
+        let delta = end - start;
+        let flat = delta.abs().cmplt(Vec2::splat(1e-6));
+        if (flat & (start.cmplt(self.origin) | start.cmpgt(grid_max))).any() {
+            return false;
+        }
+        let safe_delta = Vec2::select(flat, Vec2::ONE, delta);
+        let t0 = (self.origin - start) / safe_delta;
+        let t1 = (grid_max - start) / safe_delta;
+        let t_near = Vec2::select(flat, Vec2::ZERO, t0.min(t1));
+        let t_far = Vec2::select(flat, Vec2::ONE, t0.max(t1));
+        let t_min = t_near.max_element().max(0.0);
+        let t_max = t_far.min_element().min(1.0);
+        if t_min > t_max {
+            return false;
+        }
+
+        // Amanatides & Woo, step into whichever neighbor cell the line reaches first
+        let last = self.size.as_ivec2() - 1;
+        let mut cell =
+            ((start + delta * t_min - self.origin) / self.cell_size).floor().as_ivec2().clamp(IVec2::ZERO, last);
+        let step = IVec2::select(flat, IVec2::ZERO, delta.signum().as_ivec2());
+        let t_delta = Vec2::select(flat, Vec2::splat(1e30), self.cell_size / safe_delta.abs());
+        let boundary = self.origin
+            + (cell.as_vec2() + Vec2::select(delta.cmpgt(Vec2::ZERO), Vec2::ONE, Vec2::ZERO)) * self.cell_size;
+        let mut t_next = Vec2::select(flat, Vec2::splat(1e30), (boundary - start) / safe_delta);
+
+        let mut t_enter = -1e30;
+        for _ in 0..(self.size.x + self.size.y) {
+            let t_exit = t_next.min_element();
+            let is_last = t_exit > t_max;
+            let span = (t_enter, if is_last { 1e30 } else { t_exit });
+            if visit((cell.y * self.size.x as i32 + cell.x) as usize, span) {
+                return true;
+            }
+            if is_last {
+                break;
+            }
+            t_enter = t_exit;
+            if t_next.x < t_next.y {
+                cell.x += step.x;
+                t_next.x += t_delta.x;
+            } else {
+                cell.y += step.y;
+                t_next.y += t_delta.y;
+            }
+            if cell.cmplt(IVec2::ZERO).any() || cell.cmpgt(last).any() {
+                break;
+            }
+        }
+        false
+    }
+
+    /// Occluders that might contain `point` or pass within `distance` of it, for picking. These are the ones with a
+    /// segment in the cells around the point, or crossed by a ray to the right of it (see the even-odd rule in
+    /// `pick_hit`).
+    pub fn occluders_near(&self, point: Vec2, distance: f32) -> Vec<Entity> {
+        if self.size.x == 0 {
+            return Vec::new();
+        }
+
+        let mut segments = Vec::new();
+        let (min, max) = self.cell_range(Rect::from_center_half_size(point, Vec2::splat(distance)));
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                segments.extend_from_slice(self.cell_segments((y * self.size.x + x) as usize));
+            }
+        }
+        let grid_right = self.origin.x + self.size.x as f32 * self.cell_size;
+        self.visit_cells(point, Vec2::new(grid_right, point.y), |cell, _| {
+            segments.extend_from_slice(self.cell_segments(cell));
+            false
+        });
+
+        let mut owners: Vec<Entity> = segments.into_iter().map(|index| self.owners[index as usize]).collect();
+        owners.sort();
+        owners.dedup();
+        owners
+    }
+}
+
+// Keeps the grid in sync with the occluders in the world
+pub(crate) fn update_occluder_grid(
+    changed_q: Query<(), (With<LightOccluder>, Or<(Changed<LightOccluder>, Changed<GlobalTransform>)>)>,
+    mut removed: RemovedComponents<LightOccluder>,
+    occluder_q: Query<(Entity, &LightOccluder, &GlobalTransform)>,
+    mut grid: ResMut<OccluderGrid>,
+) {
+    let any_removed = removed.iter().count() > 0;
+    if changed_q.is_empty() && !any_removed {
+        return;
+    }
+
+    *grid = OccluderGrid::new(occluder_q.iter().flat_map(|(entity, occluder, transform)| {
+        GpuSegment::from_occluder(occluder, transform).map(move |segment| (entity, segment))
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lighting::{occlusion, transmittance, Occlusion, ShadowMode};
+
+    fn segment(a: Vec2, b: Vec2) -> GpuSegment {
+        GpuSegment {
+            a,
+            b,
+            layers: u32::MAX,
+            height: f32::MAX,
+            transmittance: GpuSegment::pack_transmittance(Vec3::splat(0.5)),
+            first: 0,
+        }
+    }
+
+    // 4 by 4 cells of 10 units, from the origin to (40, 40)
+    fn empty_grid() -> OccluderGrid {
+        OccluderGrid { cell_size: 10.0, size: UVec2::splat(4), cells: vec![GpuGridCell::default(); 16], ..default() }
+    }
+
+    fn visited(grid: &OccluderGrid, start: Vec2, end: Vec2) -> Vec<(usize, (f32, f32))> {
+        let mut cells = Vec::new();
+        grid.visit_cells(start, end, |cell, span| {
+            cells.push((cell, span));
+            false
+        });
+        cells
+    }
+
+    // The spans have to cover the line up to `end`, where it leaves the grid, without gaps or overlaps
+    fn assert_spans_tile(cells: &[(usize, (f32, f32))], end: f32) {
+        assert_eq!(cells.first().unwrap().1 .0, -1e30);
+        assert_eq!(cells.last().unwrap().1 .1, end);
+        for pair in cells.windows(2) {
+            assert_eq!(pair[0].1 .1, pair[1].1 .0);
+        }
+    }
+
+    #[test]
+    fn diagonal_ray_visits_cells_in_order() {
+        let grid = empty_grid();
+        let cells = visited(&grid, Vec2::new(5.0, 5.0), Vec2::new(35.0, 25.0));
+        let indices: Vec<usize> = cells.iter().map(|(cell, _)| *cell).collect();
+        // (0, 0), (1, 0), (1, 1), (2, 1), (2, 2), (3, 2)
+        assert_eq!(indices, [0, 1, 5, 6, 10, 11]);
+        assert_spans_tile(&cells, 1e30);
+        assert_eq!(cells[1].1, (1.0 / 6.0, 0.25));
+
+        // Going the other way visits the same cells backwards
+        let back: Vec<usize> =
+            visited(&grid, Vec2::new(35.0, 25.0), Vec2::new(5.0, 5.0)).iter().map(|(cell, _)| *cell).collect();
+        assert_eq!(back, [11, 10, 6, 5, 1, 0]);
+    }
+
+    #[test]
+    fn rays_are_clipped_to_the_grid() {
+        let grid = empty_grid();
+
+        // Starts left of the grid and ends inside it
+        let cells = visited(&grid, Vec2::new(-15.0, 5.0), Vec2::new(25.0, 5.0));
+        assert_eq!(cells.iter().map(|(cell, _)| *cell).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_spans_tile(&cells, 1e30);
+
+        // Starts below and left of the grid and leaves it at the top right
+        let cells = visited(&grid, Vec2::new(-20.0, -10.0), Vec2::new(60.0, 50.0));
+        assert_eq!(cells.first().unwrap().0, 0);
+        // Leaves through the right side of cell (3, 3), three quarters of the way along
+        assert_eq!(cells.last().unwrap().0, 15);
+        assert_spans_tile(&cells, 0.75);
+
+        // Passes the grid, or runs along outside of it
+        assert!(visited(&grid, Vec2::new(-10.0, 30.0), Vec2::new(20.0, 70.0)).is_empty());
+        assert!(visited(&grid, Vec2::new(-5.0, 0.0), Vec2::new(-5.0, 40.0)).is_empty());
+        assert!(visited(&OccluderGrid::default(), Vec2::ZERO, Vec2::ONE).is_empty());
+    }
+
+    #[test]
+    fn visit_stops_when_asked() {
+        let grid = empty_grid();
+        let mut count = 0;
+        let stopped = grid.visit_cells(Vec2::new(5.0, 5.0), Vec2::new(35.0, 5.0), |_, _| {
+            count += 1;
+            count == 2
+        });
+        assert!(stopped);
+        assert_eq!(count, 2);
+    }
+
+    // A floor from (0, 0) to (100, 0) through four cells, and small segments at the top to spread the grid out
+    fn floor_grid() -> OccluderGrid {
+        let floor = segment(Vec2::ZERO, Vec2::new(100.0, 0.0));
+        let top = (0..15).map(|i| segment(Vec2::new(i as f32 * 6.0, 100.0), Vec2::new(i as f32 * 6.0 + 1.0, 100.0)));
+        OccluderGrid::new(
+            std::iter::once(floor).chain(top).enumerate().map(|(i, segment)| (Entity::from_raw(i as u32), segment)),
+        )
+    }
+
+    #[test]
+    fn segment_in_several_cells_counts_once() {
+        let grid = floor_grid();
+        assert_eq!(grid.size, UVec2::new(4, 4));
+        assert!((0..4).all(|cell| grid.cell_segments(cell).contains(&0)));
+
+        let once = grid.segments[0].unpack_transmittance();
+        let occlusion = Occlusion { grid, ..default() };
+        // Crosses the floor in the middle of a cell, right on the border between two cells, and along all of them
+        for (start, end) in [
+            (Vec2::new(5.0, -5.0), Vec2::new(95.0, 5.0)),
+            (Vec2::new(45.0, -5.0), Vec2::new(55.0, 5.0)),
+            (Vec2::new(0.5, -0.5), Vec2::new(99.5, 0.5)),
+        ] {
+            assert_eq!(transmittance(start, end, u32::MAX, 0.0, &occlusion), once, "{start} to {end}");
+        }
+        assert_eq!(transmittance(Vec2::new(5.0, 5.0), Vec2::new(95.0, 5.0), u32::MAX, 0.0, &occlusion), Vec3::ONE);
+    }
+
+    #[test]
+    fn translucent_occluders_tint_once() {
+        // From (0, 0) to (10, 10), rects hang down from their position
+        let pane = LightOccluder { opacity: 0.5, ..LightOccluder::rect(10.0, 10.0) };
+        let occlusion = occlusion(&[(pane, Transform::from_xyz(0.0, 10.0, 0.0))], ShadowMode::Segments);
+        let once = occlusion.grid.segments[0].unpack_transmittance();
+        assert!((once - Vec3::splat(0.5)).abs().max_element() < 0.01);
+
+        // Through it, from inside of it, and through two of its corners
+        for (start, end) in [
+            (Vec2::new(-5.0, 5.0), Vec2::new(15.0, 5.0)),
+            (Vec2::new(5.0, 5.0), Vec2::new(15.0, 5.0)),
+            (Vec2::new(-5.0, 15.0), Vec2::new(15.0, -5.0)),
+        ] {
+            assert_eq!(transmittance(start, end, u32::MAX, 0.0, &occlusion), once, "{start} to {end}");
+        }
+    }
+
+    #[test]
+    fn occluders_near_finds_close_and_surrounding_occluders() {
+        let grid = floor_grid();
+        assert_eq!(grid.occluders_near(Vec2::new(50.0, 1.0), 2.0), [Entity::from_raw(0)]);
+        assert!(grid.occluders_near(Vec2::new(90.0, 50.0), 2.0).is_empty());
+
+        // A box around a point that is far from its edges is still found by the ray to the right
+        let edges = [(0.0, 0.0), (200.0, 0.0), (200.0, 200.0), (0.0, 200.0), (0.0, 0.0)]
+            .windows(2)
+            .map(|pair| (Entity::from_raw(7), segment(Vec2::from(pair[0]), Vec2::from(pair[1]))))
+            .collect::<Vec<_>>();
+        let inner = (0..16).map(|i| {
+            (Entity::from_raw(8), segment(Vec2::new(10.0 + i as f32, 10.0), Vec2::new(11.0 + i as f32, 10.0)))
+        });
+        let grid = OccluderGrid::new(edges.into_iter().chain(inner));
+        assert!(grid.size.x > 2);
+        assert_eq!(grid.occluders_near(Vec2::new(100.0, 100.0), 2.0), [Entity::from_raw(7)]);
+    }
+}
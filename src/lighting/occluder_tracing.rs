@@ -0,0 +1,265 @@
+use std::{collections::HashMap, error::Error};
+
+use bevy::{prelude::*, render::render_resource::TextureFormat};
+use serde::{Deserialize, Serialize};
+
+use super::OccluderShape;
+
+/// Which pixels of an image block light, see [`trace_occluders`]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum OccluderMask {
+    /// Pixels darker than this luminance, from 0 to 1. For opaque maps drawn on black, like the rock around the rooms
+    /// of `dungeon.png`.
+    Luminance { threshold: f32 },
+    /// Pixels with at least this alpha, from 0 to 1. For sprites and maps with transparent floors.
+    Alpha { threshold: f32 },
+    /// Pixels within `tolerance` of this color on every channel, for maps painted with a designated wall color
+    Color { color: Color, tolerance: f32 },
+}
+
+/// How the editor turns the map image into walls, saved with the level
+#[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OccluderTracing {
+    pub mask: OccluderMask,
+    /// How far the simplified outlines may stray from the traced ones, in pixels
+    pub tolerance: f32,
+    /// Outlines around fewer pixels than this are noise and get dropped
+    pub min_area: f32,
+}
+
+impl Default for OccluderTracing {
+    fn default() -> Self {
+        Self { mask: OccluderMask::Luminance { threshold: 0.02 }, tolerance: 1.0, min_area: 16.0 }
+    }
+}
+
+/// Traces the outlines of the masked pixels of an 8 bit RGBA image with marching squares and simplifies them into
+/// occluder shapes. Every shape comes with its world space position, the first point of its outline. Just like the
+/// map the image is centered on the world origin with one pixel per world unit.
+///
+/// Areas without holes become filled polygons. Outlines of areas with holes (like the walls around a room) and of
+/// the holes themselves become closed polylines, so the holes don't get filled.
+pub fn trace_occluders(
+    image: &Image,
+    settings: &OccluderTracing,
+) -> Result<Vec<(Vec2, OccluderShape)>, Box<dyn Error>> {
+    let size = image.texture_descriptor.size;
+    let solid = mask_pixels(image, &settings.mask)?;
+    if solid.iter().all(|solid| *solid) {
+        return Err("every pixel of the image is masked, there are no outlines to trace".into());
+    }
+
+    let outlines: Vec<Vec<Vec2>> = trace_contours(&solid, size.width as i32, size.height as i32)
+        .into_iter()
+        .filter(|outline| signed_area(outline).abs() >= settings.min_area)
+        .map(|outline| simplify_outline(&outline, settings.tolerance))
+        .filter(|outline| outline.len() >= 3)
+        .collect();
+
+    // The biggest outline always goes around an area, holes wind the other way around
+    let Some(outer_sign) =
+        outlines.iter().map(|outline| signed_area(outline)).max_by(|a, b| a.abs().total_cmp(&b.abs())).map(f32::signum)
+    else {
+        return Ok(Vec::new());
+    };
+    let is_hole: Vec<bool> = outlines.iter().map(|outline| signed_area(outline).signum() != outer_sign).collect();
+    let has_hole: Vec<bool> = outlines
+        .iter()
+        .enumerate()
+        .map(|(index, outline)| {
+            !is_hole[index]
+                && outlines.iter().zip(is_hole.iter()).any(|(hole, is_hole)| *is_hole && contains(outline, hole[0]))
+        })
+        .collect();
+
+    let half_size = Vec2::new(size.width as f32, size.height as f32) / 2.0;
+    let to_world = |point: Vec2| Vec2::new(point.x - half_size.x, half_size.y - point.y);
+    Ok(outlines
+        .iter()
+        .enumerate()
+        .map(|(index, outline)| {
+            let position = to_world(outline[0]);
+            let mut points: Vec<Vec2> = outline.iter().map(|point| to_world(*point) - position).collect();
+            let shape = if is_hole[index] || has_hole[index] {
+                points.push(Vec2::ZERO);
+                OccluderShape::Polyline(points)
+            } else {
+                OccluderShape::Polygon(points)
+            };
+            (position, shape)
+        })
+        .collect())
+}
+
+fn mask_pixels(image: &Image, mask: &OccluderMask) -> Result<Vec<bool>, Box<dyn Error>> {
+    match image.texture_descriptor.format {
+        TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm => {}
+        format => return Err(format!("only 8 bit RGBA images can be traced, got {format:?}").into()),
+    }
+
+    let to_f32 = |value: u8| value as f32 / u8::MAX as f32;
+    Ok(image
+        .data
+        .chunks_exact(4)
+        .map(|pixel| match mask {
+            // Rec. 709 weights on the sRGB values, so the threshold follows how bright the pixel looks
+            OccluderMask::Luminance { threshold } => {
+                0.2126 * to_f32(pixel[0]) + 0.7152 * to_f32(pixel[1]) + 0.0722 * to_f32(pixel[2]) < *threshold
+            }
+            OccluderMask::Alpha { threshold } => to_f32(pixel[3]) >= *threshold,
+            // Both are sRGB, so this compares colors like a paint program would
+            OccluderMask::Color { color, tolerance } => {
+                let color = color.as_rgba_f32();
+                (0..3).all(|channel| (to_f32(pixel[channel]) - color[channel]).abs() <= *tolerance)
+            }
+        })
+        .collect())
+}
+
+// Marching squares over the pixel centers, with y pointing down like in the image. Everything outside of the image
+// counts as empty, so every outline is closed. Outline points sit halfway between two pixel centers and are keyed by
+// twice their coordinates. Returns the outlines in pixels, with (0, 0) at the top left corner of the image.
+fn trace_contours(solid: &[bool], width: i32, height: i32) -> Vec<Vec<Vec2>> {
+    let sample = |x: i32, y: i32| x >= 0 && y >= 0 && x < width && y < height && solid[(y * width + x) as usize];
+
+    // Every outline point has exactly one successor, with the masked pixels on the same side of every edge
+    let mut next: HashMap<IVec2, IVec2> = HashMap::new();
+    let mut starts = Vec::new();
+    for y in -1..height {
+        for x in -1..width {
+            // Clockwise, the crossing at index i sits on the edge between corner i and i + 1
+            let corners = [sample(x, y), sample(x + 1, y), sample(x + 1, y + 1), sample(x, y + 1)];
+            let crossings = [
+                IVec2::new(2 * x + 1, 2 * y),
+                IVec2::new(2 * x + 2, 2 * y + 1),
+                IVec2::new(2 * x + 1, 2 * y + 2),
+                IVec2::new(2 * x, 2 * y + 1),
+            ];
+            // The outline enters the masked corners where they start and leaves where they end. Pairing every entry
+            // with the next exit keeps the diagonal corners of a saddle apart.
+            for entry in (0..4).filter(|i| !corners[*i] && corners[(i + 1) % 4]) {
+                let exit =
+                    (1..4).map(|offset| (entry + offset) % 4).find(|i| corners[*i] && !corners[(i + 1) % 4]).unwrap();
+                next.insert(crossings[entry], crossings[exit]);
+                starts.push(crossings[entry]);
+            }
+        }
+    }
+
+    let mut outlines = Vec::new();
+    for start in starts {
+        let mut outline = Vec::new();
+        let mut point = start;
+        while let Some(following) = next.remove(&point) {
+            outline.push(point.as_vec2() / 2.0 + 0.5);
+            point = following;
+        }
+        if !outline.is_empty() {
+            outlines.push(outline);
+        }
+    }
+    outlines
+}
+
+// Shoelace formula, the sign tells which way the outline winds
+fn signed_area(outline: &[Vec2]) -> f32 {
+    outline.iter().zip(outline.iter().cycle().skip(1)).map(|(a, b)| a.perp_dot(*b)).sum::<f32>() / 2.0
+}
+
+// Even-odd rule, like picking in `select_system`
+fn contains(outline: &[Vec2], point: Vec2) -> bool {
+    outline
+        .iter()
+        .zip(outline.iter().cycle().skip(1))
+        .filter(|(a, b)| {
+            (a.y > point.y) != (b.y > point.y) && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+        })
+        .count()
+        % 2
+        == 1
+}
+
+// Ramer-Douglas-Peucker for a closed outline, split at the point furthest away from the first one
+fn simplify_outline(outline: &[Vec2], tolerance: f32) -> Vec<Vec2> {
+    let furthest = (0..outline.len())
+        .max_by(|a, b| outline[0].distance_squared(outline[*a]).total_cmp(&outline[0].distance_squared(outline[*b])))
+        .unwrap_or(0);
+
+    let mut closed = outline.to_vec();
+    closed.push(outline[0]);
+    let mut keep = vec![false; closed.len()];
+    keep[0] = true;
+    keep[furthest] = true;
+
+    // Keeps the point furthest away from the line between the ends of a span, if it's too far, and looks at both
+    // halves again
+    let mut spans = vec![(0, furthest), (furthest, closed.len() - 1)];
+    while let Some((first, last)) = spans.pop() {
+        let (a, b) = (closed[first], closed[last]);
+        let Some((index, distance)) = (first + 1..last)
+            .map(|index| (index, distance_to_segment(closed[index], a, b)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        else {
+            continue;
+        };
+        if distance > tolerance {
+            keep[index] = true;
+            spans.push((first, index));
+            spans.push((index, last));
+        }
+    }
+
+    closed.pop();
+    closed.into_iter().zip(keep).filter(|(_, keep)| *keep).map(|(point, _)| point).collect()
+}
+
+fn distance_to_segment(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let along = (point - a).dot(b - a) / (b - a).length_squared().max(f32::EPSILON);
+    point.distance(a + (b - a) * along.clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::{Extent3d, TextureDimension};
+
+    use super::*;
+
+    // An opaque grey 16 by 16 image with a black 6 by 6 block, like a pillar on a dungeon floor
+    fn opaque_map() -> Image {
+        let data = (0..16 * 16)
+            .flat_map(|index| {
+                let (x, y) = (index % 16, index / 16);
+                let value = if (4..10).contains(&x) && (2..8).contains(&y) { 0 } else { 160 };
+                [value, value, value, u8::MAX]
+            })
+            .collect();
+        Image::new(
+            Extent3d { width: 16, height: 16, ..default() },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    #[test]
+    fn default_mask_traces_dark_pixels_of_opaque_maps() {
+        let outlines = trace_occluders(&opaque_map(), &OccluderTracing::default()).unwrap();
+        assert_eq!(outlines.len(), 1);
+        let (position, OccluderShape::Polygon(points)) = &outlines[0] else {
+            panic!("{:?}", outlines[0]);
+        };
+        // Columns 4 to 10 and rows 2 to 8, with the image centered on the origin and y pointing up
+        let bounds = points
+            .iter()
+            .fold(Rect::from_corners(*position, *position), |bounds, point| bounds.union_point(*point + *position));
+        assert_eq!(bounds, Rect::new(-4.0, 6.0, 2.0, 0.0));
+        assert_eq!(points.len(), 4);
+    }
+
+    #[test]
+    fn alpha_mask_refuses_opaque_maps() {
+        let settings = OccluderTracing { mask: OccluderMask::Alpha { threshold: 0.5 }, ..default() };
+        assert!(trace_occluders(&opaque_map(), &settings).is_err());
+    }
+}
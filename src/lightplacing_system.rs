@@ -1,43 +1,198 @@
 use bevy::prelude::*;
 use bevy_mod_picking::PickableBundle;
-use bevy_prototype_lyon::{prelude::{ShapeBundle, GeometryBuilder, Fill, Stroke}, shapes};
+use bevy_pancam::PanCam;
+use bevy_prototype_lyon::{prelude::{ShapeBundle, GeometryBuilder, Fill, Stroke, Path}, shapes};
 
-use crate::{actions, GameState, components::Deleteable, lighting::LightSource};
+use crate::{actions, GameState, components::Deleteable, history::{Edit, EditHistory, EditObject}, level::LightData, lighting::{Falloff, LightShape, LightSource, Spot, ALL_LIGHT_LAYERS, DEFAULT_LIGHT_HEIGHT}};
 
 pub struct LightPlaceSystem;
 
 impl Plugin for LightPlaceSystem {
     fn build(&self, app: &mut App) {
-        app.add_system(handle_place_lights.in_set(OnUpdate(GameState::Playing)));
-    }
-}
-
-pub fn handle_place_lights(actions: Res<actions::Actions>, mut commands: Commands) {
-    let curs = actions.world_cursor_position;
-
-    if let Some(curs) = curs {
-        if actions.left_click && actions.current_tool() == Some(actions::Tool::PlaceLight) {
-            commands.spawn((ShapeBundle {
-                 path: GeometryBuilder::build_as(&shapes::Rectangle{
-                     extents: Vec2::new(10.0, 10.0),
-                     origin: shapes::RectangleOrigin::Center,
-                     ..default()
-                 }),
-                 transform: Transform::from_translation(Vec3::new(curs.x, curs.y, 0.0)),
-                 ..default()
-             },
-             Fill::color(Color::WHITE),
-             Stroke::new(Color::WHITE, 1.0),
-             PickableBundle::default(),
-             Deleteable,
-             LightSource {
-                position: Vec2::new(curs.x, curs.y),
-                color: Vec4::new(1.0, 1.0, 1.0, 1.0),
-                intensity: 2.0,
-                radius: 100.0,
-                is_active: 1
-             }
-            ));
-         }
+        app.add_system(handle_place_lights.after(actions::update_mouse_click).in_set(OnUpdate(GameState::Playing)))
+            .add_system(handle_draw_area_lights.after(actions::update_mouse_click).in_set(OnUpdate(GameState::Playing)));
+    }
+}
+
+// How far the mouse has to be dragged before a placed light becomes a spotlight
+const AIM_DISTANCE: f32 = 10.0;
+
+// Click to place a point light, or press and drag to place a spotlight aimed at the cursor
+pub fn handle_place_lights(actions: Res<actions::Actions>, mut commands: Commands, mut history: ResMut<EditHistory>, mut placing: Local<Option<Entity>>, mut light_q: Query<(&mut LightSource, &mut Transform, &mut Path)>, mut pancam_q: Query<&mut PanCam>) {
+    let Some(curs) = actions.world_cursor_position else {
+        return;
+    };
+
+    if let Some(entity) = *placing {
+        let Ok((mut light, mut transform, mut path)) = light_q.get_mut(entity) else {
+            *placing = None;
+            return;
+        };
+
+        let aim = curs - light.position;
+        if aim.length() > AIM_DISTANCE {
+            if light.spot.is_none() {
+                light.spot = Some(Spot::default());
+                *path = light_path(&light);
+            }
+            transform.rotation = Quat::from_rotation_z(aim.y.atan2(aim.x));
+        }
+
+        if !actions.left_held {
+            history.record(Edit::Create {
+                entity,
+                object: EditObject::Light(LightData::from_transform(&transform, &light, None)),
+            });
+            *placing = None;
+            pancam_q.for_each_mut(|mut pancam| pancam.enabled = true);
+        }
+    } else if actions.left_click && actions.current_tool() == Some(actions::Tool::PlaceLight) {
+        *placing = Some(spawn_light(&mut commands, &LightData {
+            position: curs,
+            rotation: 0.0,
+            light: new_light(curs, LightShape::Point),
+            animation: None,
+        }));
+        pancam_q.for_each_mut(|mut pancam| pancam.enabled = false);
+    }
+}
+
+// What a freshly placed light looks like
+fn new_light(position: Vec2, shape: LightShape) -> LightSource {
+    LightSource {
+        position,
+        color: Vec4::new(1.0, 1.0, 1.0, 1.0),
+        intensity: 2.0,
+        radius: 100.0,
+        is_active: 1,
+        source_radius: 5.0,
+        falloff: Falloff::InverseSquare,
+        spot: None,
+        shape,
+        height: DEFAULT_LIGHT_HEIGHT,
+        is_static: false,
+        layers: ALL_LIGHT_LAYERS,
+    }
+}
+
+/// A line or area light that is still being drawn, `start` is where the first click was
+#[derive(Component)]
+pub(crate) struct PreliminaryLight {
+    start: Vec2,
+}
+
+// Draws line and area lights like walls: the first click starts the shape, the second one finishes it.
+// Escape or switching to another tool throws the unfinished light away.
+fn handle_draw_area_lights(mut actions: ResMut<actions::Actions>, keyboard_input: Res<Input<KeyCode>>, mut commands: Commands, mut history: ResMut<EditHistory>, mut preliminary_q: Query<(Entity, &PreliminaryLight, &mut LightSource, &mut Transform, &mut Path)>, mut pancam_q: Query<&mut PanCam>) {
+    let Some(curs) = actions.world_cursor_position else {
+        return;
+    };
+
+    let Ok((entity, preliminary, mut light, mut transform, mut path)) = preliminary_q.get_single_mut() else {
+        let shape = match actions.current_tool() {
+            Some(actions::Tool::PlaceLineLight) => LightShape::Line { length: 0.0 },
+            Some(actions::Tool::PlaceAreaLight) => LightShape::Rect { width: 0.0, height: 0.0 },
+            _ => return,
+        };
+        if actions.left_click {
+            let entity = spawn_light(&mut commands, &LightData {
+                position: curs,
+                rotation: 0.0,
+                light: new_light(curs, shape),
+                animation: None,
+            });
+            commands.entity(entity).insert(PreliminaryLight { start: curs });
+            pancam_q.for_each_mut(|mut pancam| pancam.enabled = false);
+        }
+        return;
+    };
+
+    let tool = match light.shape {
+        LightShape::Line { .. } => actions::Tool::PlaceLineLight,
+        _ => actions::Tool::PlaceAreaLight,
+    };
+    if keyboard_input.just_pressed(KeyCode::Escape) || actions.current_tool() != Some(tool) {
+        commands.entity(entity).despawn_recursive();
+        pancam_q.for_each_mut(|mut pancam| pancam.enabled = true);
+        return;
+    }
+
+    // Line lights run from the first click to the cursor, area lights span the rectangle between them
+    let span = curs - preliminary.start;
+    light.position = preliminary.start + span / 2.0;
+    match light.shape {
+        LightShape::Line { ref mut length } => {
+            *length = span.length();
+            transform.rotation = Quat::from_rotation_z(span.y.atan2(span.x));
+        }
+        LightShape::Rect { ref mut width, ref mut height } => {
+            *width = span.x.abs();
+            *height = span.y.abs();
+        }
+        LightShape::Point => {}
+    }
+    transform.translation.x = light.position.x;
+    transform.translation.y = light.position.y;
+    *path = light_path(&light);
+
+    if actions.left_click {
+        commands.entity(entity).remove::<PreliminaryLight>();
+        history.record(Edit::Create {
+            entity,
+            object: EditObject::Light(LightData::from_transform(&transform, &light, None)),
+        });
+        actions.revert_to_previous_tool();
+        pancam_q.for_each_mut(|mut pancam| pancam.enabled = true);
+    }
+}
+
+// Spawns the marker for a light
+pub fn spawn_light(commands: &mut Commands, light: &LightData) -> Entity {
+    let mut entity = commands.spawn((ShapeBundle {
+         path: light_path(&light.light),
+         transform: light.transform(),
+         ..default()
+     },
+     light_fill(&light.light),
+     Stroke::new(Color::WHITE, 1.0),
+     PickableBundle::default(),
+     Deleteable,
+     LightSource {
+         position: light.position,
+         ..light.light
+     }
+    ));
+    if let Some(animation) = &light.animation {
+        entity.insert(animation.clone());
+    }
+    entity.id()
+}
+
+// Point lights are a square, spotlights a triangle pointing where they shine. Line and area lights show their emitter.
+pub fn light_path(light: &LightSource) -> Path {
+    match (light.shape, light.spot) {
+        (LightShape::Line { length }, _) => GeometryBuilder::build_as(&shapes::Line(Vec2::new(-length / 2.0, 0.0), Vec2::new(length / 2.0, 0.0))),
+        (LightShape::Rect { width, height }, _) => GeometryBuilder::build_as(&shapes::Rectangle {
+            extents: Vec2::new(width, height),
+            origin: shapes::RectangleOrigin::Center,
+            ..default()
+        }),
+        (LightShape::Point, Some(_)) => GeometryBuilder::build_as(&shapes::Polygon {
+            points: vec![Vec2::new(7.0, 0.0), Vec2::new(-5.0, 5.0), Vec2::new(-5.0, -5.0)],
+            closed: true,
+        }),
+        (LightShape::Point, None) => GeometryBuilder::build_as(&shapes::Rectangle{
+            extents: Vec2::new(10.0, 10.0),
+            origin: shapes::RectangleOrigin::Center,
+            ..default()
+        }),
+    }
+}
+
+// Area lights are only tinted, so the map underneath stays visible
+pub fn light_fill(light: &LightSource) -> Fill {
+    match light.shape {
+        LightShape::Rect { .. } => Fill::color(Color::rgba(1.0, 1.0, 1.0, 0.3)),
+        _ => Fill::color(Color::WHITE),
     }
 }
@@ -40,6 +40,8 @@ pub struct TextureAssets {
     #[asset(path = "textures/bevy.png")]
     pub texture_bevy: Handle<Image>,
 
+    /// Its normal and emissive maps are optional, so they are loaded by `setup_map` instead, see
+    /// [`crate::map::set_lit_map`]
     #[asset(path = "textures/dungeon.png")]
     pub dungeon_map: Handle<Image>,
 }
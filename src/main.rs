@@ -5,15 +5,21 @@ use bevy::prelude::*;
 use bevy::window::PrimaryWindow;
 use bevy::winit::WinitWindows;
 use bevy::DefaultPlugins;
-use bevy_game::GamePlugin;
+use bevy_game::{bake_level, GamePlugin};
 use std::io::Cursor;
+use std::path::Path;
 use winit::window::Icon;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("bake") {
+        bake(&args[1..]);
+        return;
+    }
+
     App::new()
         .insert_resource(Msaa::Off)
-        .insert_resource(ClearColor(Color::rgb(0.4, 0.4, 0.4)))
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
                 title: "Bevy game".to_string(), // ToDo
@@ -29,6 +35,40 @@ fn main() {
         .run();
 }
 
+// `bake <level.ron> [--output <lightmap.png|lightmap.ktx2>] [--assets <dir>] [--normal-map <normals.png>]` bakes
+// the static lights of a level without opening a window. The normal map is relative to the asset directory.
+fn bake(args: &[String]) {
+    let usage = "Usage: bake <level.ron> [--output <lightmap.png|lightmap.ktx2>] [--assets <dir>] [--normal-map <normals.png>]";
+    let Some(level) = args.first() else {
+        eprintln!("{usage}");
+        std::process::exit(2);
+    };
+
+    let mut output = None;
+    let mut assets = Path::new("assets");
+    let mut normal_map = None;
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        match (option.as_str(), options.next()) {
+            ("--output", Some(path)) => output = Some(Path::new(path)),
+            ("--assets", Some(path)) => assets = Path::new(path),
+            ("--normal-map", Some(path)) => normal_map = Some(Path::new(path)),
+            _ => {
+                eprintln!("{usage}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    match bake_level(Path::new(level), assets, output, normal_map) {
+        Ok(path) => println!("Baked lightmap to {}", path.display()),
+        Err(err) => {
+            eprintln!("Could not bake {level}: {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
 // Sets the icon on windows and X11
 fn set_window_icon(
     windows: NonSend<WinitWindows>,
@@ -1,83 +1,104 @@
-use bevy::{
-    prelude::*,
-    render::{render_resource::Extent3d, view::RenderLayers},
-    sprite::MaterialMesh2dBundle,
-};
+use std::path::{Path, PathBuf};
+
+use bevy::{asset::LoadState, prelude::*, render::render_resource::TextureFormat};
 use bevy_prototype_lyon::prelude::ShapePlugin;
 
 use crate::{
-    lighting::{LightingMaterial, WrappedBool, WrappedF32, WrappedVec2, WrappedVec4},
+    lighting::LightingTextures,
     loading::TextureAssets,
     GameState,
 };
 
-#[derive(Component, Default, Clone, Copy, Debug)]
-pub struct MapMarker {
-    pub width: u32,
-    pub height: u32,
-}
+/// The sprite showing the map image, lit by the lighting pass like everything else
+#[derive(Component)]
+pub struct MapBackground;
 
 pub struct MapPlugin;
 
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(setup_map.in_schedule(OnEnter(GameState::Playing)));
+        app.add_system(setup_map.in_schedule(OnEnter(GameState::Playing)))
+            .add_system(update_optional_maps.in_set(OnUpdate(GameState::Playing)));
     }
 }
 
 fn setup_map(
     mut commands: Commands,
     textures: Res<TextureAssets>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    assets: Res<Assets<Image>>,
-    mut post_processing_materials: ResMut<Assets<LightingMaterial>>,
+    asset_server: Res<AssetServer>,
+    mut lighting_textures: ResMut<LightingTextures>,
 ) {
     let img_handle = textures.dungeon_map.clone();
-    commands.spawn(SpriteBundle {
+    commands.spawn((SpriteBundle {
         texture: img_handle.clone(),
         transform: Transform::from_translation(Vec3::new(0., 0., 0.)),
         ..Default::default()
-    });
+    }, MapBackground));
 
-    let image = assets.get(&img_handle).unwrap();
+    match asset_server.get_handle_path(&img_handle) {
+        Some(path) => set_lit_map(&mut lighting_textures, &asset_server, img_handle.clone(), path.path()),
+        None => lighting_textures.map = img_handle,
+    }
+}
 
-    let width = image.texture_descriptor.size.width;
-    let height = image.texture_descriptor.size.height;
+/// Where the normal map of the map image at `path` is, next to it with `_normal` after its name
+pub fn normal_map_path(path: &Path) -> PathBuf {
+    companion_map_path(path, "normal")
+}
 
-    let size = Extent3d {
-        width: width,
-        height: height,
-        ..default()
-    };
+/// Where the emissive map of the map image at `path` is, next to it with `_emissive` after its name. It holds the
+/// glowing parts of the map like lava and is black everywhere else.
+pub fn emissive_map_path(path: &Path) -> PathBuf {
+    companion_map_path(path, "emissive")
+}
 
-    let quad_handle = meshes.add(Mesh::from(shape::Quad::new(Vec2::new(
-        size.width as f32,
-        size.height as f32,
-    ))));
+fn companion_map_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}_{}.png", stem, suffix))
+}
+
+/// Lights `map`, loaded from `path` in the asset directory, with the normal and emissive maps next to it. Both are
+/// optional, `update_optional_maps` drops them if their files don't exist. They are loaded outside of the loading
+/// state, a missing file would fail the whole asset collection there.
+pub fn set_lit_map(lighting_textures: &mut LightingTextures, asset_server: &AssetServer, map: Handle<Image>, path: &Path) {
+    lighting_textures.map = map;
+    lighting_textures.normal_map = Some(asset_server.load(normal_map_path(path)));
+    lighting_textures.emissive_map = Some(asset_server.load(emissive_map_path(path)));
+}
 
-    let material_handle = post_processing_materials.add(LightingMaterial {
-        colors: [WrappedVec4 { value: Vec4::ZERO }; 64],
-        positions: [WrappedVec2 { value: Vec2::ZERO }; 64],
-        intensities: [WrappedF32 { value: 0.0 }; 64],
-        radiuses: [WrappedF32 { value: 0.0 }; 64],
-        is_active: [WrappedBool { value: 0 }; 64],
-        occluders: [WrappedVec4 { value: Vec4::ZERO }; 64],
-        exists: [WrappedBool { value: 0 }; 64],
-        source_image: img_handle.clone(),
-    });
+// Drops the optional maps whose files don't exist, the lighting pass only samples the loaded ones until then.
+// Pngs get loaded as sRGB, but the normals have to be read as they are.
+fn update_optional_maps(
+    asset_server: Res<AssetServer>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+    mut images: ResMut<Assets<Image>>,
+    mut lighting_textures: ResMut<LightingTextures>,
+) {
+    for event in image_events.iter() {
+        let AssetEvent::Created { handle } = event else {
+            continue;
+        };
+        if lighting_textures.normal_map.as_ref() != Some(handle) {
+            continue;
+        }
+        if let Some(normal_map) = images.get_mut(handle) {
+            if normal_map.texture_descriptor.format == TextureFormat::Rgba8UnormSrgb {
+                normal_map.texture_descriptor.format = TextureFormat::Rgba8Unorm;
+            }
+        }
+    }
 
-    commands.spawn(
-        (MaterialMesh2dBundle {
-            mesh: quad_handle.into(),
-            material: material_handle,
-            transform: Transform {
-                translation: Vec3::new(0.0, 0.0, 1.0),
-                ..default()
-            },
-            ..default()
-        }, MapMarker {
-            width,
-            height,
-        }),
-    );
+    // The path of the missing file
+    let failed = |map: &Option<Handle<Image>>| {
+        let handle = map.as_ref().filter(|handle| asset_server.get_load_state(*handle) == LoadState::Failed)?;
+        Some(asset_server.get_handle_path(handle).map(|path| path.path().to_path_buf()).unwrap_or_default())
+    };
+    if let Some(path) = failed(&lighting_textures.normal_map) {
+        info!("There is no {:?}, the map is lit flat", path);
+        lighting_textures.normal_map = None;
+    }
+    if let Some(path) = failed(&lighting_textures.emissive_map) {
+        info!("There is no {:?}, nothing on the map glows", path);
+        lighting_textures.emissive_map = None;
+    }
 }
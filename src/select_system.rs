@@ -0,0 +1,442 @@
+use bevy::prelude::*;
+use bevy_pancam::PanCam;
+use bevy_prototype_lyon::prelude::*;
+
+use crate::{
+    actions::{update_mouse_click, Actions, Tool},
+    components::Deleteable,
+    history::{Edit, EditHistory, EditObject},
+    lighting::{update_occluder_grid, LightAnimation, LightOccluder, LightSource, OccluderGrid, OccluderShape},
+    wall::wall_path,
+    GameState,
+};
+
+pub struct SelectSystemPlugin;
+
+/// The select tool: click to select, shift-click to add/remove, drag on empty space to box-select,
+/// drag a selection to move it and drag the corner handles of a single wall to resize it
+impl Plugin for SelectSystemPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SelectDrag>().add_systems(
+            (handle_select_tool, update_selection_outline, update_resize_handles, update_selection_box)
+                .chain()
+                .after(update_mouse_click)
+                .after(update_occluder_grid)
+                .in_set(OnUpdate(GameState::Playing)),
+        );
+    }
+}
+
+/// Marks the walls and lights picked with the select tool
+#[derive(Component)]
+pub struct Selected;
+
+#[derive(Component)]
+struct ResizeHandle {
+    corner: usize,
+}
+
+#[derive(Component)]
+struct SelectionBox;
+
+#[derive(Resource, Default)]
+struct SelectDrag(Option<Drag>);
+
+enum Drag {
+    Move { last: Vec2, before: Vec<(Entity, EditObject)> },
+    Resize { entity: Entity, fixed_corner: Vec2, before: EditObject },
+    Box { start: Vec2 },
+}
+
+const SELECTED_COLOR: Color = Color::YELLOW;
+const HANDLE_SIZE: f32 = 8.0;
+// Point lights are drawn as 10x10 squares, give them (and the emitters of line and area lights) a bit of slack
+const LIGHT_PICK_SIZE: f32 = 12.0;
+// Polylines have no area, so they are picked by their distance to the cursor
+const POLYLINE_PICK_DISTANCE: f32 = 4.0;
+
+/// World space bounds of a wall or light, used for picking
+pub fn pick_bounds(transform: &Transform, occluder: Option<&LightOccluder>, light: Option<&LightSource>) -> Rect {
+    match occluder {
+        Some(occluder) => occluder.bounds(&GlobalTransform::from(*transform)),
+        None => {
+            let half_size = light_pick_half_size(light);
+            [Vec2::new(-1.0, -1.0), Vec2::new(1.0, -1.0), Vec2::ONE, Vec2::new(-1.0, 1.0)]
+                .map(|corner| transform.transform_point((corner * half_size).extend(0.0)).truncate())
+                .into_iter()
+                .fold(Rect::from_center_size(transform.translation.truncate(), Vec2::ZERO), |bounds, corner| {
+                    bounds.union_point(corner)
+                })
+        }
+    }
+}
+
+// Half size of the box around a light that can be clicked, in the light's local space
+fn light_pick_half_size(light: Option<&LightSource>) -> Vec2 {
+    light.map_or(Vec2::ZERO, |light| light.shape.half_extents()) + Vec2::splat(LIGHT_PICK_SIZE / 2.0)
+}
+
+/// Is `point` on the wall or light?
+pub fn pick_hit(
+    transform: &Transform,
+    occluder: Option<&LightOccluder>,
+    light: Option<&LightSource>,
+    point: Vec2,
+) -> bool {
+    let Some(occluder) = occluder else {
+        let local = transform.compute_affine().inverse().transform_point3(point.extend(0.0)).truncate();
+        return local.abs().cmple(light_pick_half_size(light)).all();
+    };
+
+    let segments = occluder.segments(&GlobalTransform::from(*transform));
+    if occluder.is_closed() {
+        // Even-odd rule, count the edges a ray to the right of the point crosses
+        segments
+            .iter()
+            .filter(|(a, b)| {
+                (a.y > point.y) != (b.y > point.y) && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+            })
+            .count()
+            % 2
+            == 1
+    } else {
+        segments.iter().any(|(a, b)| {
+            let along = (point - *a).dot(*b - *a) / (*b - *a).length_squared().max(f32::EPSILON);
+            point.distance(*a + (*b - *a) * along.clamp(0.0, 1.0)) <= POLYLINE_PICK_DISTANCE
+        })
+    }
+}
+
+// Resize handles only make sense for rect walls that aren't rotated
+fn resizable_size(transform: &Transform, occluder: &LightOccluder) -> Option<Vec2> {
+    match occluder.shape {
+        OccluderShape::Rect { width, height } if transform.rotation == Quat::IDENTITY => Some(Vec2::new(width, height)),
+        _ => None,
+    }
+}
+
+// Corners of a rect wall, ordered so that the opposite of corner `i` is `3 - i`
+fn wall_corners(position: Vec2, size: Vec2) -> [Vec2; 4] {
+    [
+        position,
+        position + Vec2::new(size.x, 0.0),
+        position + Vec2::new(0.0, -size.y),
+        position + Vec2::new(size.x, -size.y),
+    ]
+}
+
+// Everything the select tool can pick, move and resize
+type EditableQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static mut Transform,
+        Option<&'static mut LightOccluder>,
+        Option<&'static mut LightSource>,
+        Option<&'static mut Path>,
+        Option<&'static LightAnimation>,
+    ),
+    With<Deleteable>,
+>;
+
+fn handle_select_tool(
+    mut commands: Commands,
+    actions: Res<Actions>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut drag: ResMut<SelectDrag>,
+    mut history: ResMut<EditHistory>,
+    mut pancam_q: Query<&mut PanCam>,
+    mut editable_q: EditableQuery,
+    selected_q: Query<Entity, With<Selected>>,
+    occluder_grid: Res<OccluderGrid>,
+) {
+    let Some(cursor) = actions.world_cursor_position else {
+        return;
+    };
+
+    if actions.current_tool() != Some(Tool::Select) {
+        if drag.0.take().is_some() {
+            set_pancam_enabled(&mut pancam_q, true);
+        }
+        return;
+    }
+
+    if actions.left_click {
+        let shift = keyboard_input.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+        drag.0 = start_drag(&mut commands, cursor, shift, &editable_q, &selected_q, &occluder_grid);
+        if drag.0.is_some() {
+            set_pancam_enabled(&mut pancam_q, false);
+        }
+    } else if actions.left_held {
+        match drag.0.as_mut() {
+            Some(Drag::Move { last, before }) => {
+                let delta = cursor - *last;
+                *last = cursor;
+                for (entity, _) in before.iter() {
+                    if let Ok((_, mut transform, _, light, ..)) = editable_q.get_mut(*entity) {
+                        transform.translation += delta.extend(0.0);
+                        if let Some(mut light) = light {
+                            light.position = transform.translation.truncate();
+                        }
+                    }
+                }
+            }
+            Some(Drag::Resize { entity, fixed_corner, .. }) => {
+                if let Ok((_, mut transform, Some(mut occluder), _, Some(mut path), _)) = editable_q.get_mut(*entity) {
+                    let rect = Rect::from_corners(*fixed_corner, cursor);
+                    transform.translation.x = rect.min.x;
+                    transform.translation.y = rect.max.y;
+                    occluder.shape = OccluderShape::Rect { width: rect.width(), height: rect.height() };
+                    *path = wall_path(&occluder);
+                }
+            }
+            Some(Drag::Box { .. }) | None => {}
+        }
+    } else if actions.left_released {
+        let Some(finished) = drag.0.take() else {
+            return;
+        };
+        set_pancam_enabled(&mut pancam_q, true);
+
+        match finished {
+            Drag::Move { before, .. } => {
+                let edits: Vec<Edit> = before
+                    .into_iter()
+                    .filter_map(|(entity, before)| {
+                        let (_, transform, occluder, light, _, animation) = editable_q.get(entity).ok()?;
+                        let after = EditObject::from_components(transform, occluder, light, animation)?;
+                        Some(Edit::Modify { entity, before, after })
+                    })
+                    .collect();
+                if !edits.is_empty() && actually_moved(&edits) {
+                    history.record(Edit::Group(edits));
+                }
+            }
+            Drag::Resize { entity, before, .. } => {
+                if let Ok((_, transform, occluder, light, _, animation)) = editable_q.get(entity) {
+                    if let Some(after) = EditObject::from_components(transform, occluder, light, animation) {
+                        history.record(Edit::Modify { entity, before, after });
+                    }
+                }
+            }
+            Drag::Box { start } => {
+                let selection = Rect::from_corners(start, cursor);
+                if selection.width() < 1.0 && selection.height() < 1.0 {
+                    return;
+                }
+                for (entity, transform, occluder, light, ..) in editable_q.iter() {
+                    if !pick_bounds(transform, occluder, light).intersect(selection).is_empty() {
+                        commands.entity(entity).insert(Selected);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn start_drag(
+    commands: &mut Commands,
+    cursor: Vec2,
+    shift: bool,
+    editable_q: &EditableQuery,
+    selected_q: &Query<Entity, With<Selected>>,
+    occluder_grid: &OccluderGrid,
+) -> Option<Drag> {
+    let selected: Vec<Entity> = selected_q.iter().collect();
+
+    // The resize handles of a single selected wall come first
+    if let [entity] = selected[..] {
+        if let Ok((_, transform, Some(occluder), ..)) = editable_q.get(entity) {
+            let corners = resizable_size(transform, occluder)
+                .map(|size| wall_corners(transform.translation.truncate(), size))
+                .unwrap_or_default();
+            if let Some(corner) = corners.iter().position(|corner| corner.distance(cursor) <= HANDLE_SIZE) {
+                return Some(Drag::Resize {
+                    entity,
+                    fixed_corner: corners[3 - corner],
+                    before: EditObject::from_components(transform, Some(occluder), None, None)?,
+                });
+            }
+        }
+    }
+
+    // Only walls with an edge near the cursor or to the right of it can be hit, the grid knows which ones those are
+    let wall_candidates = occluder_grid.occluders_near(cursor, POLYLINE_PICK_DISTANCE);
+
+    // Lights sit on top of walls, and smaller walls on top of bigger ones
+    let hit = editable_q
+        .iter()
+        .filter(|(entity, _, occluder, ..)| occluder.is_none() || wall_candidates.binary_search(entity).is_ok())
+        .filter(|(_, transform, occluder, light, ..)| pick_hit(transform, *occluder, *light, cursor))
+        .min_by(|(_, transform_a, occluder_a, ..), (_, transform_b, occluder_b, ..)| {
+            let area_a =
+                occluder_a.map_or(0.0, |_| pick_bounds(transform_a, *occluder_a, None).size().length_squared());
+            let area_b =
+                occluder_b.map_or(0.0, |_| pick_bounds(transform_b, *occluder_b, None).size().length_squared());
+            area_a.total_cmp(&area_b)
+        })
+        .map(|(entity, ..)| entity);
+
+    let Some(hit) = hit else {
+        if !shift {
+            for entity in selected.iter() {
+                commands.entity(*entity).remove::<Selected>();
+            }
+        }
+        return Some(Drag::Box { start: cursor });
+    };
+
+    let hit_selected = selected.contains(&hit);
+    if shift {
+        if hit_selected {
+            commands.entity(hit).remove::<Selected>();
+        } else {
+            commands.entity(hit).insert(Selected);
+        }
+        return None;
+    }
+
+    let moving = if hit_selected {
+        selected
+    } else {
+        for entity in selected.iter() {
+            commands.entity(*entity).remove::<Selected>();
+        }
+        commands.entity(hit).insert(Selected);
+        vec![hit]
+    };
+
+    let before = moving
+        .into_iter()
+        .filter_map(|entity| {
+            let (_, transform, occluder, light, _, animation) = editable_q.get(entity).ok()?;
+            Some((entity, EditObject::from_components(transform, occluder, light, animation)?))
+        })
+        .collect();
+
+    Some(Drag::Move { last: cursor, before })
+}
+
+// A click on an already selected object shouldn't end up in the history
+fn actually_moved(edits: &[Edit]) -> bool {
+    edits.iter().any(|edit| match edit {
+        Edit::Modify { before, after, .. } => position(before) != position(after),
+        _ => true,
+    })
+}
+
+fn position(object: &EditObject) -> Vec2 {
+    match object {
+        EditObject::Wall(wall) => wall.position,
+        EditObject::Light(light) => light.position,
+    }
+}
+
+fn set_pancam_enabled(pancam_q: &mut Query<&mut PanCam>, enabled: bool) {
+    if let Ok(mut pancam) = pancam_q.get_single_mut() {
+        pancam.enabled = enabled;
+    }
+}
+
+fn update_selection_outline(
+    added_q: Query<Entity, Added<Selected>>,
+    mut removed: RemovedComponents<Selected>,
+    mut stroke_q: Query<(&mut Stroke, Option<&LightSource>)>,
+) {
+    for entity in added_q.iter() {
+        if let Ok((mut stroke, _)) = stroke_q.get_mut(entity) {
+            *stroke = Stroke::new(SELECTED_COLOR, 3.0);
+        }
+    }
+
+    // Back to the strokes walls and lights are spawned with
+    for entity in removed.iter() {
+        if let Ok((mut stroke, light)) = stroke_q.get_mut(entity) {
+            *stroke = match light {
+                Some(_) => Stroke::new(Color::WHITE, 1.0),
+                None => Stroke::new(Color::BLACK, 1.0),
+            };
+        }
+    }
+}
+
+fn update_resize_handles(
+    mut commands: Commands,
+    selection_q: Query<(), With<Selected>>,
+    wall_q: Query<(&Transform, &LightOccluder), With<Selected>>,
+    mut handle_q: Query<(Entity, &ResizeHandle, &mut Transform), Without<Selected>>,
+) {
+    let single_wall = match (selection_q.iter().count(), wall_q.get_single()) {
+        (1, Ok((transform, occluder))) => resizable_size(transform, occluder).map(|size| (transform, size)),
+        _ => None,
+    };
+
+    let Some((transform, size)) = single_wall else {
+        for (entity, _, _) in handle_q.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    };
+
+    let corners = wall_corners(transform.translation.truncate(), size);
+    if handle_q.is_empty() {
+        for (corner, position) in corners.iter().enumerate() {
+            commands.spawn((
+                ShapeBundle {
+                    path: GeometryBuilder::build_as(&shapes::Rectangle {
+                        extents: Vec2::splat(HANDLE_SIZE),
+                        origin: shapes::RectangleOrigin::Center,
+                        ..default()
+                    }),
+                    transform: Transform::from_translation(position.extend(2.0)),
+                    ..default()
+                },
+                Fill::color(SELECTED_COLOR),
+                ResizeHandle { corner },
+            ));
+        }
+    } else {
+        for (_, handle, mut handle_transform) in handle_q.iter_mut() {
+            handle_transform.translation = corners[handle.corner].extend(2.0);
+        }
+    }
+}
+
+fn update_selection_box(
+    mut commands: Commands,
+    actions: Res<Actions>,
+    drag: Res<SelectDrag>,
+    mut box_q: Query<(Entity, &mut Path), With<SelectionBox>>,
+) {
+    let corners = match (&drag.0, actions.world_cursor_position) {
+        (Some(Drag::Box { start }), Some(cursor)) => Some((*start, cursor)),
+        _ => None,
+    };
+
+    let Some((start, cursor)) = corners else {
+        for (entity, _) in box_q.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    };
+
+    let outline = GeometryBuilder::build_as(&shapes::Polygon {
+        points: vec![start, Vec2::new(cursor.x, start.y), cursor, Vec2::new(start.x, cursor.y)],
+        closed: true,
+    });
+
+    if let Ok((_, mut path)) = box_q.get_single_mut() {
+        *path = outline;
+    } else {
+        commands.spawn((
+            ShapeBundle {
+                path: outline,
+                transform: Transform::from_translation(Vec3::new(0.0, 0.0, 3.0)),
+                ..default()
+            },
+            Stroke::new(SELECTED_COLOR, 1.0),
+            SelectionBox,
+        ));
+    }
+}
@@ -0,0 +1,89 @@
+use std::time::Instant;
+
+use bevy::prelude::*;
+
+use crate::{
+    history::{Edit, EditHistory, EditObject},
+    level::WallData,
+    lighting::{trace_occluders, LightOccluder, LightingTextures, OccluderTracing},
+    wall::{spawn_wall, TracedWall},
+    GameState,
+};
+
+pub struct TraceSystemPlugin;
+
+/// Turns the masked pixels of the map image into walls with Ctrl+T or the button in the tool bar, so existing map
+/// art casts shadows. The mask is set in [`OccluderTracing`], from the settings panel. Tracing again replaces the
+/// walls of the last trace, and all of it is undone at once with Ctrl+Z.
+impl Plugin for TraceSystemPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<OccluderTracing>()
+            .add_event::<TraceOccluders>()
+            .add_systems((handle_trace_shortcut, trace_map_occluders).chain().in_set(OnUpdate(GameState::Playing)));
+    }
+}
+
+pub struct TraceOccluders;
+
+fn handle_trace_shortcut(keyboard_input: Res<Input<KeyCode>>, mut trace_events: EventWriter<TraceOccluders>) {
+    let ctrl = keyboard_input.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    if ctrl && keyboard_input.just_pressed(KeyCode::T) {
+        trace_events.send(TraceOccluders);
+    }
+}
+
+fn trace_map_occluders(
+    mut commands: Commands,
+    mut events: EventReader<TraceOccluders>,
+    settings: Res<OccluderTracing>,
+    lighting_textures: Res<LightingTextures>,
+    images: Res<Assets<Image>>,
+    traced_q: Query<(Entity, &Transform, &LightOccluder), With<TracedWall>>,
+    mut history: ResMut<EditHistory>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    let Some(image) = images.get(&lighting_textures.map) else {
+        warn!("Can't trace before the map is loaded");
+        return;
+    };
+
+    let start = Instant::now();
+    let outlines = match trace_occluders(image, &settings) {
+        Ok(outlines) => outlines,
+        Err(err) => {
+            error!("Could not trace the map: {}", err);
+            return;
+        }
+    };
+
+    // The walls of the last trace are replaced, hand drawn ones stay
+    let mut edits: Vec<Edit> = traced_q
+        .iter()
+        .map(|(entity, transform, occluder)| {
+            commands.entity(entity).despawn_recursive();
+            Edit::Delete {
+                entity,
+                object: EditObject::Wall(WallData { traced: true, ..WallData::from_transform(transform, occluder) }),
+            }
+        })
+        .collect();
+    let replaced = edits.len();
+
+    edits.extend(outlines.into_iter().map(|(position, shape)| {
+        let wall = WallData { position, rotation: 0.0, occluder: LightOccluder { shape, ..default() }, traced: true };
+        Edit::Create { entity: spawn_wall(&mut commands, &wall), object: EditObject::Wall(wall) }
+    }));
+    info!(
+        "Traced {} walls in {:.1?}, replacing {} from the last trace",
+        edits.len() - replaced,
+        start.elapsed(),
+        replaced
+    );
+
+    if !edits.is_empty() {
+        history.record(Edit::Group(edits));
+    }
+}
@@ -1,14 +1,21 @@
 use bevy::{prelude::*, a11y::{AccessibilityNode, accesskit::{Role, NodeBuilder}}, input::mouse::{MouseWheel, MouseScrollUnit}, reflect::erased_serde::__private::serde::__private::de};
 
-use crate::{loading::FontAssets, GameState, actions::Actions, actions::Tool};
+use bevy_prototype_lyon::prelude::Path;
+
+use crate::{loading::FontAssets, GameState, actions::{update_mouse_click, Actions}, actions::Tool, level::{LoadLevel, SaveLevel}, bake_system::BakeLighting, trace_system::TraceOccluders};
+use crate::{history::{Edit, EditHistory, EditObject}, lighting::{DistanceField, Falloff, FalloffCurves, GlobalIllumination, LightAnimation, LightBlink, LightFlicker, LightGradient, LightOccluder, LightPulse, LightResolution, LightSource, LightingQuality, OccluderMask, OccluderShape, OccluderTracing, ShadowMode, Spot, ALL_LIGHT_LAYERS}, lightplacing_system::light_path, select_system::Selected, wall::wall_path};
 
 pub struct UiPlugin;
 
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
        app.add_system(setup_ui.in_schedule(OnEnter(GameState::Playing)))
+       .add_system(setup_inspector_panel.in_schedule(OnEnter(GameState::Playing)))
        .add_system(update_debug_control_text.in_set(OnUpdate(GameState::Playing)))
-       .add_system(handle_tool_buttons.in_set(OnUpdate(GameState::Playing)));
+       .add_system(handle_tool_buttons.before(update_mouse_click).in_set(OnUpdate(GameState::Playing)))
+       .add_systems((handle_inspector_buttons, update_inspector_panel).chain().in_set(OnUpdate(GameState::Playing)))
+       .add_system(setup_settings_panel.in_schedule(OnEnter(GameState::Playing)))
+       .add_systems((handle_settings_buttons, update_settings_panel).chain().in_set(OnUpdate(GameState::Playing)));
     }
 }
 
@@ -16,8 +23,15 @@ impl Plugin for UiPlugin {
 pub enum ButtonType {
     Select,
     PlaceWall,
+    PlacePolygon,
     PlaceLight,
-    Delete
+    PlaceLineLight,
+    PlaceAreaLight,
+    Delete,
+    Save,
+    Load,
+    Bake,
+    Trace
 }
 
 fn setup_ui(mut commands: Commands, font_assets: Res<FontAssets>) {
@@ -40,65 +54,17 @@ fn setup_ui(mut commands: Commands, font_assets: Res<FontAssets>) {
             },
             ..default()
         }).with_children(|button_par| {
-            button_par.spawn((ButtonBundle {
-                style: Style {
-                    size: Size::new(Val::Px(150.0), Val::Px(65.0)),
-                    // horizontally center child text
-                    justify_content: JustifyContent::Center,
-                    // vertically center child text
-                    align_items: AlignItems::Center,
-                    ..default()
-                },
-                background_color: BackgroundColor(Color::BLACK),
-                ..default()
-            },ButtonType::Select)).with_children(|buttons| {
-                buttons.spawn(TextBundle::from_section("Select", TextStyle { font: font_assets.fira_sans.clone(), font_size: 12.0, color: Color::WHITE }));
-            });
-
-            button_par.spawn((ButtonBundle {
-                style: Style {
-                    size: Size::new(Val::Px(150.0), Val::Px(65.0)),
-                    // horizontally center child text
-                    justify_content: JustifyContent::Center,
-                    // vertically center child text
-                    align_items: AlignItems::Center,
-                    ..default()
-                },
-                background_color: BackgroundColor(Color::BLACK),
-                ..default()
-            },ButtonType::PlaceWall)).with_children(|buttons| {
-                buttons.spawn(TextBundle::from_section("Place Wall", TextStyle { font: font_assets.fira_sans.clone(), font_size: 12.0, color: Color::WHITE }));
-            });
-
-            button_par.spawn((ButtonBundle {
-                style: Style {
-                    size: Size::new(Val::Px(150.0), Val::Px(65.0)),
-                    // horizontally center child text
-                    justify_content: JustifyContent::Center,
-                    // vertically center child text
-                    align_items: AlignItems::Center,
-                    ..default()
-                },
-                background_color: BackgroundColor(Color::BLACK),
-                ..default()
-            },ButtonType::PlaceLight)).with_children(|buttons| {
-                buttons.spawn(TextBundle::from_section("Place Light", TextStyle { font: font_assets.fira_sans.clone(), font_size: 12.0, color: Color::WHITE }));
-            });
-
-            button_par.spawn((ButtonBundle {
-                style: Style {
-                    size: Size::new(Val::Px(150.0), Val::Px(65.0)),
-                    // horizontally center child text
-                    justify_content: JustifyContent::Center,
-                    // vertically center child text
-                    align_items: AlignItems::Center,
-                    ..default()
-                },
-                background_color: BackgroundColor(Color::BLACK),
-                ..default()
-            }, ButtonType::Delete)).with_children(|buttons| {
-                buttons.spawn(TextBundle::from_section("Delete", TextStyle { font: font_assets.fira_sans.clone(), font_size: 12.0, color: Color::WHITE }));
-            });
+            spawn_button(button_par, "Select", ButtonType::Select, &font_assets.fira_sans);
+            spawn_button(button_par, "Place Wall", ButtonType::PlaceWall, &font_assets.fira_sans);
+            spawn_button(button_par, "Place Polygon", ButtonType::PlacePolygon, &font_assets.fira_sans);
+            spawn_button(button_par, "Place Light", ButtonType::PlaceLight, &font_assets.fira_sans);
+            spawn_button(button_par, "Place Line Light", ButtonType::PlaceLineLight, &font_assets.fira_sans);
+            spawn_button(button_par, "Place Area Light", ButtonType::PlaceAreaLight, &font_assets.fira_sans);
+            spawn_button(button_par, "Delete", ButtonType::Delete, &font_assets.fira_sans);
+            spawn_button(button_par, "Save", ButtonType::Save, &font_assets.fira_sans);
+            spawn_button(button_par, "Load", ButtonType::Load, &font_assets.fira_sans);
+            spawn_button(button_par, "Bake Lighting", ButtonType::Bake, &font_assets.fira_sans);
+            spawn_button(button_par, "Trace Walls", ButtonType::Trace, &font_assets.fira_sans);
         });
 
         parent.spawn((TextBundle::from_sections([
@@ -108,6 +74,24 @@ fn setup_ui(mut commands: Commands, font_assets: Res<FontAssets>) {
           
 }
 
+// A tool bar button with its label centered on it
+fn spawn_button(parent: &mut ChildBuilder, label: &str, marker: ButtonType, font: &Handle<Font>) {
+    parent.spawn((ButtonBundle {
+        style: Style {
+            size: Size::new(Val::Px(150.0), Val::Px(65.0)),
+            // horizontally center child text
+            justify_content: JustifyContent::Center,
+            // vertically center child text
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        background_color: BackgroundColor(Color::BLACK),
+        ..default()
+    }, marker)).with_children(|buttons| {
+        buttons.spawn(TextBundle::from_section(label, TextStyle { font: font.clone(), font_size: 12.0, color: Color::WHITE }));
+    });
+}
+
 #[derive(Component, Default)]
 struct ScrollingList {
     position: f32,
@@ -121,30 +105,67 @@ fn update_debug_control_text(mut debug_text: Query<&mut Text, With<DebugControls
     text.sections[0].value = format!("Clicked?: {:?},Current Tool: {:?}, Cursor X: {:.2}, Y: {:.2}, World Pos Cursor X: {:.2}, Y: {:.2}", actions.left_click,actions.current_tool().as_ref().unwrap_or(&Tool::None), actions.cursor_position_raw.unwrap_or(Vec2::ZERO).x, actions.cursor_position_raw.unwrap_or(Vec2::ZERO).y, actions.world_cursor_position.unwrap_or(Vec2::ZERO).x, actions.world_cursor_position.unwrap_or(Vec2::ZERO).y)
 }
 
-fn handle_tool_buttons(mut interaction_query: Query<(&Interaction, &ButtonType),(Changed<Interaction>, With<Button>)>, mut actions: ResMut<Actions>) {
+// Every button counts as a ui click, so clicking e.g. the inspector doesn't also select or place something behind it
+fn handle_tool_buttons(mut interaction_query: Query<(&Interaction, Option<&ButtonType>),(Changed<Interaction>, With<Button>)>, mut actions: ResMut<Actions>, mut save_events: EventWriter<SaveLevel>, mut load_events: EventWriter<LoadLevel>, mut bake_events: EventWriter<BakeLighting>, mut trace_events: EventWriter<TraceOccluders>) {
     let mut just_set_ui_clicked = false;
     for interaction in interaction_query.iter() {
         match interaction.1 {
-            ButtonType::Select => {
+            None => {},
+            Some(ButtonType::Select) => {
                 if let Interaction::Clicked = interaction.0 {
                     actions.update_tool(Tool::Select);
                 }
             },
-            ButtonType::PlaceWall => {
+            Some(ButtonType::PlaceWall) => {
                 if let Interaction::Clicked = interaction.0 {
                     actions.update_tool(Tool::BuildWall);
 
                 }
             },
-            ButtonType::PlaceLight => {
+            Some(ButtonType::PlacePolygon) => {
+                if let Interaction::Clicked = interaction.0 {
+                    actions.update_tool(Tool::BuildPolygon);
+                }
+            },
+            Some(ButtonType::PlaceLight) => {
                 if let Interaction::Clicked = interaction.0 {
                     actions.update_tool(Tool::PlaceLight);
                 }
             },
-            ButtonType::Delete => {
+            Some(ButtonType::PlaceLineLight) => {
+                if let Interaction::Clicked = interaction.0 {
+                    actions.update_tool(Tool::PlaceLineLight);
+                }
+            },
+            Some(ButtonType::PlaceAreaLight) => {
+                if let Interaction::Clicked = interaction.0 {
+                    actions.update_tool(Tool::PlaceAreaLight);
+                }
+            },
+            Some(ButtonType::Delete) => {
                 if let Interaction::Clicked = interaction.0 {
                     actions.update_tool(Tool::Delete);
                 }
+            },
+            Some(ButtonType::Save) => {
+                if let Interaction::Clicked = interaction.0 {
+                    save_events.send(SaveLevel);
+                }
+            },
+            Some(ButtonType::Load) => {
+                if let Interaction::Clicked = interaction.0 {
+                    load_events.send(LoadLevel);
+                }
+            },
+            Some(ButtonType::Bake) => {
+                if let Interaction::Clicked = interaction.0 {
+                    bake_events.send(BakeLighting);
+                }
+            },
+            Some(ButtonType::Trace) => {
+                if let Interaction::Clicked = interaction.0 {
+                    trace_events.send(TraceOccluders);
+                }
             }
         }
         actions.ui_just_clicked = true;
@@ -155,6 +176,614 @@ fn handle_tool_buttons(mut interaction_query: Query<(&Interaction, &ButtonType),
     }
 }
 
+/// The values of the selected light or wall that can be changed in the inspector panel
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+enum InspectorField {
+    Red,
+    Green,
+    Blue,
+    Intensity,
+    Radius,
+    Active,
+    SourceRadius,
+    Falloff,
+    Cone,
+    InnerCone,
+    Elevation,
+    Static,
+    Layers,
+    Animation,
+    Width,
+    Height,
+    Rotation,
+    Opacity,
+}
+
+impl InspectorField {
+    const LIGHT_FIELDS: [InspectorField; 14] = [
+        InspectorField::Red,
+        InspectorField::Green,
+        InspectorField::Blue,
+        InspectorField::Intensity,
+        InspectorField::Radius,
+        InspectorField::Active,
+        InspectorField::SourceRadius,
+        InspectorField::Falloff,
+        InspectorField::Cone,
+        InspectorField::InnerCone,
+        InspectorField::Elevation,
+        InspectorField::Static,
+        InspectorField::Layers,
+        InspectorField::Animation,
+    ];
+    // The color, elevation and layers are shared with the light fields, walls use the color for their transmittance
+    const WALL_FIELDS: [InspectorField; 4] =
+        [InspectorField::Width, InspectorField::Height, InspectorField::Rotation, InspectorField::Opacity];
+
+    fn label(self) -> &'static str {
+        match self {
+            InspectorField::Red => "Red",
+            InspectorField::Green => "Green",
+            InspectorField::Blue => "Blue",
+            InspectorField::Intensity => "Intensity",
+            InspectorField::Radius => "Radius",
+            InspectorField::Active => "Active",
+            InspectorField::SourceRadius => "Softness",
+            InspectorField::Falloff => "Falloff",
+            InspectorField::Cone => "Cone",
+            InspectorField::InnerCone => "Inner Cone",
+            InspectorField::Elevation => "Elevation",
+            InspectorField::Static => "Static",
+            InspectorField::Layers => "Layers",
+            InspectorField::Animation => "Animation",
+            InspectorField::Width => "Width",
+            InspectorField::Height => "Height",
+            InspectorField::Rotation => "Rotation",
+            InspectorField::Opacity => "Opacity",
+        }
+    }
+
+    // Circle walls reuse the radius field, width and height only exist on rect walls. Distance field shadows can't
+    // show layers, wall heights or translucent walls, so those fields are hidden while they are on.
+    // The color of a wall is the color of the light it lets through.
+    fn applies_to(self, light: Option<&LightSource>, occluder: Option<&LightOccluder>, shadows: ShadowMode) -> bool {
+        let shape = occluder.map(|occluder| &occluder.shape);
+        let spot = light.and_then(|light| light.spot);
+        let field_shadows = matches!(shadows, ShadowMode::DistanceField { .. });
+        match self {
+            InspectorField::Layers | InspectorField::Opacity if field_shadows => false,
+            InspectorField::Elevation | InspectorField::Red | InspectorField::Green | InspectorField::Blue
+                if field_shadows =>
+            {
+                light.is_some()
+            }
+            InspectorField::Radius => light.is_some() || matches!(shape, Some(OccluderShape::Circle { .. })),
+            InspectorField::Width | InspectorField::Height => matches!(shape, Some(OccluderShape::Rect { .. })),
+            InspectorField::InnerCone => spot.is_some(),
+            InspectorField::Rotation => occluder.is_some() || spot.is_some(),
+            InspectorField::Red
+            | InspectorField::Green
+            | InspectorField::Blue
+            | InspectorField::Elevation
+            | InspectorField::Layers => light.is_some() || occluder.is_some(),
+            InspectorField::Opacity => occluder.is_some(),
+            _ => light.is_some(),
+        }
+    }
+
+    fn value(
+        self,
+        transform: &Transform,
+        light: Option<&LightSource>,
+        occluder: Option<&LightOccluder>,
+        animation: Option<&LightAnimation>,
+    ) -> String {
+        // Walls share the color, elevation and layer fields with lights
+        if let (None, Some(occluder)) = (light, occluder) {
+            match self {
+                InspectorField::Red => return format!("{:.2}", occluder.transmittance.r()),
+                InspectorField::Green => return format!("{:.2}", occluder.transmittance.g()),
+                InspectorField::Blue => return format!("{:.2}", occluder.transmittance.b()),
+                InspectorField::Opacity => return format!("{:.1}", occluder.opacity),
+                InspectorField::Layers => return layers_label(occluder.layers),
+                InspectorField::Elevation => {
+                    return occluder.height.map_or("Full".to_string(), |height| format!("{:.0}", height));
+                }
+                _ => {}
+            }
+        }
+
+        let shape = occluder.map(|occluder| &occluder.shape);
+        match (self, light, shape) {
+            (InspectorField::Red, Some(light), _) => format!("{:.2}", light.color.x),
+            (InspectorField::Green, Some(light), _) => format!("{:.2}", light.color.y),
+            (InspectorField::Blue, Some(light), _) => format!("{:.2}", light.color.z),
+            (InspectorField::Intensity, Some(light), _) => format!("{:.1}", light.intensity),
+            (InspectorField::Radius, Some(light), _) => format!("{:.0}", light.radius),
+            (InspectorField::Active, Some(light), _) => if light.is_active != 0 { "On" } else { "Off" }.to_string(),
+            (InspectorField::SourceRadius, Some(light), _) => format!("{:.0}", light.source_radius),
+            (InspectorField::Falloff, Some(light), _) => match light.falloff {
+                Falloff::InverseSquare => "1/d²".to_string(),
+                Falloff::Linear => "Linear".to_string(),
+                Falloff::Smoothstep => "Smooth".to_string(),
+                Falloff::Curve(index) => format!("Curve {}", index),
+            },
+            // Shows the whole opening angle, not the angle from the direction to the edge
+            (InspectorField::Cone, Some(light), _) => match light.spot {
+                Some(spot) => format!("{:.0}°", spot.outer_angle.to_degrees() * 2.0),
+                None => "Omni".to_string(),
+            },
+            (InspectorField::InnerCone, Some(LightSource { spot: Some(spot), .. }), _) => {
+                format!("{:.0}°", spot.inner_angle.to_degrees() * 2.0)
+            }
+            (InspectorField::Elevation, Some(light), _) => format!("{:.0}", light.height),
+            (InspectorField::Static, Some(light), _) => if light.is_static { "Baked" } else { "Dynamic" }.to_string(),
+            (InspectorField::Layers, Some(light), _) => layers_label(light.layers),
+            (InspectorField::Animation, Some(_), _) => animation_label(animation).to_string(),
+            (InspectorField::Radius, _, Some(OccluderShape::Circle { radius })) => format!("{:.0}", radius),
+            (InspectorField::Width, _, Some(OccluderShape::Rect { width, .. })) => format!("{:.0}", width.abs()),
+            (InspectorField::Height, _, Some(OccluderShape::Rect { height, .. })) => format!("{:.0}", height.abs()),
+            (InspectorField::Rotation, _, _) => {
+                format!("{:.0}°", transform.rotation.to_euler(EulerRot::XYZ).2.to_degrees())
+            }
+            _ => String::new(),
+        }
+    }
+
+    // `direction` is 1 for the + and -1 for the - button
+    fn step_light(self, light: &mut LightSource, direction: f32, curve_count: usize) {
+        let step_color = |value: f32| (value + 0.1 * direction).clamp(0.0, 1.0);
+        match self {
+            InspectorField::Red => light.color.x = step_color(light.color.x),
+            InspectorField::Green => light.color.y = step_color(light.color.y),
+            InspectorField::Blue => light.color.z = step_color(light.color.z),
+            InspectorField::Intensity => light.intensity = (light.intensity + 0.5 * direction).max(0.0),
+            InspectorField::Radius => light.radius = (light.radius + 10.0 * direction).max(0.0),
+            InspectorField::Active => light.is_active = if light.is_active != 0 { 0 } else { 1 },
+            InspectorField::SourceRadius => light.source_radius = (light.source_radius + direction).max(0.0),
+            InspectorField::Falloff => {
+                // Cycles through the built in falloffs followed by every custom curve
+                let falloffs: Vec<Falloff> = [Falloff::InverseSquare, Falloff::Linear, Falloff::Smoothstep]
+                    .into_iter()
+                    .chain((0..curve_count as u32).map(Falloff::Curve))
+                    .collect();
+                let current = falloffs.iter().position(|falloff| *falloff == light.falloff).unwrap_or(0);
+                let next = (current as i32 + direction as i32).rem_euclid(falloffs.len() as i32);
+                light.falloff = falloffs[next as usize];
+            }
+            InspectorField::Cone => {
+                // Opening the cone all the way turns the spotlight back into a point light
+                let outer = light.spot.map_or(180.0, |spot| spot.outer_angle.to_degrees()) + 5.0 * direction;
+                light.spot = (outer < 180.0).then(|| {
+                    let outer_angle = outer.max(5.0).to_radians();
+                    let inner_angle = light.spot.unwrap_or_default().inner_angle.min(outer_angle);
+                    Spot { inner_angle, outer_angle }
+                });
+            }
+            InspectorField::InnerCone => {
+                if let Some(spot) = light.spot.as_mut() {
+                    spot.inner_angle = (spot.inner_angle + (5.0 * direction).to_radians()).clamp(0.0, spot.outer_angle);
+                }
+            }
+            InspectorField::Elevation => light.height = (light.height + 10.0 * direction).max(1.0),
+            InspectorField::Static => light.is_static = !light.is_static,
+            InspectorField::Layers => light.layers = step_layers(light.layers, direction),
+            // Animations are a component of their own, see `step_animation`
+            InspectorField::Animation
+            | InspectorField::Width
+            | InspectorField::Height
+            | InspectorField::Rotation
+            | InspectorField::Opacity => {}
+        }
+    }
+
+    fn step_occluder(self, occluder: &mut LightOccluder, direction: f32) {
+        // Walls built from right to left or bottom to top have a negative size, + should still make them bigger
+        let step_size = |value: f32| {
+            let size = (value.abs() + 5.0 * direction).max(1.0);
+            if value < 0.0 { -size } else { size }
+        };
+        let step_fraction = |value: f32| (value + 0.1 * direction).clamp(0.0, 1.0);
+        let transmittance = &mut occluder.transmittance;
+        match (self, &mut occluder.shape) {
+            (InspectorField::Red, _) => {
+                transmittance.set_r(step_fraction(transmittance.r()));
+            }
+            (InspectorField::Green, _) => {
+                transmittance.set_g(step_fraction(transmittance.g()));
+            }
+            (InspectorField::Blue, _) => {
+                transmittance.set_b(step_fraction(transmittance.b()));
+            }
+            (InspectorField::Opacity, _) => occluder.opacity = step_fraction(occluder.opacity),
+            (InspectorField::Width, OccluderShape::Rect { width, .. }) => *width = step_size(*width),
+            (InspectorField::Height, OccluderShape::Rect { height, .. }) => *height = step_size(*height),
+            (InspectorField::Radius, OccluderShape::Circle { radius }) => *radius = step_size(*radius),
+            (InspectorField::Layers, _) => occluder.layers = step_layers(occluder.layers, direction),
+            // Walls taller than the highest step block every light
+            (InspectorField::Elevation, _) => {
+                let height = occluder.height.unwrap_or(MAX_WALL_HEIGHT + 10.0) + 10.0 * direction;
+                occluder.height = (height <= MAX_WALL_HEIGHT).then(|| height.max(0.0));
+            }
+            _ => {}
+        }
+    }
+}
+
+// Highest wall height the inspector steps to before switching to walls that block every light
+const MAX_WALL_HEIGHT: f32 = 200.0;
+
+// The layer masks the inspector steps through: every layer, each of the first eight on its own and no layer at all
+const LAYER_STEPS: [u32; 10] = [ALL_LIGHT_LAYERS, 1, 2, 4, 8, 16, 32, 64, 128, 0];
+
+fn step_layers(layers: u32, direction: f32) -> u32 {
+    let current = LAYER_STEPS.iter().position(|step| *step == layers).unwrap_or(0);
+    LAYER_STEPS[(current as i32 + direction as i32).rem_euclid(LAYER_STEPS.len() as i32) as usize]
+}
+
+// The animations the inspector steps through, anything else set up in code shows up as "Custom"
+const ANIMATION_STEPS: [&str; 5] = ["None", "Flicker", "Pulse", "Colors", "Blink"];
+
+fn animation_label(animation: Option<&LightAnimation>) -> &'static str {
+    let Some(animation) = animation else {
+        return "None";
+    };
+    match (&animation.flicker, &animation.pulse, &animation.gradient, &animation.blink) {
+        (Some(_), None, None, None) => "Flicker",
+        (None, Some(_), None, None) => "Pulse",
+        (None, None, Some(_), None) => "Colors",
+        (None, None, None, Some(_)) => "Blink",
+        (None, None, None, None) => "None",
+        _ => "Custom",
+    }
+}
+
+fn step_animation(animation: Option<&LightAnimation>, direction: f32) -> Option<LightAnimation> {
+    let current = ANIMATION_STEPS.iter().position(|step| *step == animation_label(animation)).unwrap_or(0);
+    let next = ANIMATION_STEPS[(current as i32 + direction as i32).rem_euclid(ANIMATION_STEPS.len() as i32) as usize];
+    match next {
+        // Every torch gets its own seed, so they don't flicker in sync
+        "Flicker" => Some(LightAnimation { flicker: Some(LightFlicker::random()), ..default() }),
+        "Pulse" => Some(LightAnimation { pulse: Some(LightPulse::default()), ..default() }),
+        "Colors" => Some(LightAnimation { gradient: Some(LightGradient::rainbow()), ..default() }),
+        "Blink" => Some(LightAnimation { blink: Some(LightBlink::default()), ..default() }),
+        _ => None,
+    }
+}
+
+fn layers_label(layers: u32) -> String {
+    match layers {
+        ALL_LIGHT_LAYERS => "All".to_string(),
+        0 => "None".to_string(),
+        layers if layers.is_power_of_two() => format!("{}", layers.trailing_zeros() + 1),
+        layers => format!("{:#x}", layers),
+    }
+}
+
+#[derive(Component)]
+struct InspectorPanel;
+
+#[derive(Component)]
+struct InspectorValue(InspectorField);
+
+#[derive(Component)]
+struct InspectorButton {
+    field: InspectorField,
+    direction: f32,
+}
+
+fn setup_inspector_panel(mut commands: Commands, font_assets: Res<FontAssets>) {
+    let text_style = TextStyle { font: font_assets.fira_sans.clone(), font_size: 16.0, color: Color::WHITE };
+
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect { right: Val::Px(0.0), top: Val::Px(0.0), ..default() },
+            size: Size::width(Val::Px(220.0)),
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(8.0)),
+            display: Display::None,
+            ..default()
+        },
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.8)),
+        ..default()
+    }, InspectorPanel)).with_children(|panel| {
+        for field in InspectorField::LIGHT_FIELDS.into_iter().chain(InspectorField::WALL_FIELDS) {
+            spawn_field_row(panel, field.label(), &text_style, field, InspectorValue(field), |direction| InspectorButton { field, direction });
+        }
+    });
+}
+
+// A row of the inspector or settings panel: the label, a - button, the value and a + button. `button` makes the
+// marker of a button from its direction.
+fn spawn_field_row<B: Bundle>(panel: &mut ChildBuilder, label: &str, text_style: &TextStyle, row_marker: impl Bundle, value_marker: impl Bundle, button: impl Fn(f32) -> B) {
+    panel.spawn((NodeBundle {
+        style: Style {
+            justify_content: JustifyContent::SpaceBetween,
+            align_items: AlignItems::Center,
+            margin: UiRect::vertical(Val::Px(2.0)),
+            ..default()
+        },
+        ..default()
+    }, row_marker)).with_children(|row| {
+        row.spawn(TextBundle {
+            text: Text::from_section(label, text_style.clone()),
+            style: Style { size: Size::width(Val::Px(80.0)), ..default() },
+            ..default()
+        });
+        let step_button = |row: &mut ChildBuilder, label: &str, direction: f32| {
+            row.spawn((ButtonBundle {
+                style: Style {
+                    size: Size::new(Val::Px(28.0), Val::Px(24.0)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: BackgroundColor(Color::DARK_GRAY),
+                ..default()
+            }, button(direction))).with_children(|button| {
+                button.spawn(TextBundle::from_section(label, text_style.clone()));
+            });
+        };
+        step_button(row, "-", -1.0);
+        row.spawn((TextBundle {
+            text: Text::from_section("", text_style.clone()),
+            style: Style { size: Size::width(Val::Px(50.0)), ..default() },
+            ..default()
+        }, value_marker));
+        step_button(row, "+", 1.0);
+    });
+}
+
+// Only shown while exactly one light or wall is selected
+fn update_inspector_panel(
+    selected_q: Query<(&Transform, Option<&LightSource>, Option<&LightOccluder>, Option<&LightAnimation>), With<Selected>>,
+    mut panel_q: Query<&mut Style, (With<InspectorPanel>, Without<InspectorField>)>,
+    mut row_q: Query<(&mut Style, &InspectorField)>,
+    mut value_q: Query<(&mut Text, &InspectorValue)>,
+    illumination: Res<GlobalIllumination>,
+) {
+    let selected = selected_q.get_single().ok().filter(|(_, light, occluder, _)| light.is_some() || occluder.is_some());
+
+    for mut style in panel_q.iter_mut() {
+        style.display = if selected.is_some() { Display::Flex } else { Display::None };
+    }
+
+    let Some((transform, light, occluder, animation)) = selected else {
+        return;
+    };
+
+    for (mut style, field) in row_q.iter_mut() {
+        style.display = if field.applies_to(light, occluder, illumination.shadows) { Display::Flex } else { Display::None };
+    }
+
+    for (mut text, value) in value_q.iter_mut() {
+        text.sections[0].value = value.0.value(transform, light, occluder, animation);
+    }
+}
+
+fn handle_inspector_buttons(
+    mut commands: Commands,
+    interaction_q: Query<(&Interaction, &InspectorButton), Changed<Interaction>>,
+    mut selected_q: Query<(Entity, &mut Transform, Option<&mut LightSource>, Option<&mut LightOccluder>, Option<&mut Path>, Option<&LightAnimation>), With<Selected>>,
+    mut history: ResMut<EditHistory>,
+    falloff_curves: Res<FalloffCurves>,
+) {
+    for (interaction, button) in interaction_q.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        let Ok((entity, mut transform, mut light, mut occluder, mut path, animation)) = selected_q.get_single_mut() else {
+            continue;
+        };
+        let mut animation = animation.cloned();
+        let Some(before) = EditObject::from_components(&transform, occluder.as_deref(), light.as_deref(), animation.as_ref()) else {
+            continue;
+        };
+
+        if button.field == InspectorField::Rotation {
+            transform.rotate_z((15.0 * button.direction).to_radians());
+        }
+        if button.field == InspectorField::Animation && light.is_some() {
+            animation = step_animation(animation.as_ref(), button.direction);
+            match &animation {
+                Some(animation) => commands.entity(entity).insert(animation.clone()),
+                None => commands.entity(entity).remove::<LightAnimation>(),
+            };
+        }
+        if let Some(light) = light.as_mut() {
+            button.field.step_light(light, button.direction, falloff_curves.curves.len());
+            if let Some(path) = path.as_mut() {
+                **path = light_path(light);
+            }
+        }
+        if let Some(occluder) = occluder.as_mut() {
+            button.field.step_occluder(occluder, button.direction);
+            if let Some(path) = path.as_mut() {
+                **path = wall_path(occluder);
+            }
+        }
+
+        if let Some(after) = EditObject::from_components(&transform, occluder.as_deref(), light.as_deref(), animation.as_ref()) {
+            history.record(Edit::Modify { entity, before, after });
+        }
+    }
+}
+
+/// Settings of the whole level that can be changed in the settings panel
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+enum SettingsField {
+    Quality,
+    Shadows,
+    TraceMask,
+    TraceThreshold,
+}
+
+impl SettingsField {
+    const FIELDS: [SettingsField; 4] =
+        [SettingsField::Quality, SettingsField::Shadows, SettingsField::TraceMask, SettingsField::TraceThreshold];
+
+    fn label(self) -> &'static str {
+        match self {
+            SettingsField::Quality => "Light Quality",
+            SettingsField::Shadows => "Shadows",
+            SettingsField::TraceMask => "Trace Mask",
+            SettingsField::TraceThreshold => "Threshold",
+        }
+    }
+
+    fn value(self, tracing: &OccluderTracing, illumination: &GlobalIllumination, quality: &LightingQuality) -> String {
+        match (self, tracing.mask) {
+            (SettingsField::Quality, _) => format!("{:?}", quality.resolution),
+            (SettingsField::Shadows, _) => match illumination.shadows {
+                ShadowMode::Segments => "Segments".to_string(),
+                ShadowMode::DistanceField { .. } => "Field".to_string(),
+            },
+            (SettingsField::TraceMask, OccluderMask::Luminance { .. }) => "Dark".to_string(),
+            (SettingsField::TraceMask, OccluderMask::Alpha { .. }) => "Alpha".to_string(),
+            (SettingsField::TraceMask, OccluderMask::Color { .. }) => "Color".to_string(),
+            (SettingsField::TraceThreshold, OccluderMask::Luminance { threshold } | OccluderMask::Alpha { threshold }) => {
+                format!("{:.2}", threshold)
+            }
+            (SettingsField::TraceThreshold, OccluderMask::Color { tolerance, .. }) => format!("{:.2}", tolerance),
+        }
+    }
+
+    // `direction` is 1 for the + and -1 for the - button
+    fn step(
+        self,
+        tracing: &mut OccluderTracing,
+        illumination: &mut GlobalIllumination,
+        quality: &mut LightingQuality,
+        direction: f32,
+    ) {
+        // Dark maps are usually near black, their threshold takes finer steps
+        let step_fraction = |value: &mut f32, step: f32| *value = (*value + step * direction).clamp(0.0, 1.0);
+        match (self, &mut tracing.mask) {
+            // + goes towards full resolution
+            (SettingsField::Quality, _) => {
+                let resolutions = LightResolution::ALL;
+                let current = resolutions.iter().position(|resolution| *resolution == quality.resolution).unwrap_or(0);
+                let next = (current as i32 - direction as i32).clamp(0, resolutions.len() as i32 - 1);
+                quality.resolution = resolutions[next as usize];
+            }
+            (SettingsField::Shadows, _) => {
+                illumination.shadows = match illumination.shadows {
+                    ShadowMode::Segments => ShadowMode::DistanceField { texel_size: FIELD_TEXEL_SIZE },
+                    ShadowMode::DistanceField { .. } => ShadowMode::Segments,
+                };
+            }
+            (SettingsField::TraceMask, mask) => {
+                let kind = std::mem::discriminant(mask);
+                let current = TRACE_MASK_STEPS.iter().position(|step| std::mem::discriminant(step) == kind).unwrap_or(0);
+                *mask = TRACE_MASK_STEPS[(current as i32 + direction as i32).rem_euclid(TRACE_MASK_STEPS.len() as i32) as usize];
+            }
+            (SettingsField::TraceThreshold, OccluderMask::Luminance { threshold }) => step_fraction(threshold, 0.01),
+            (SettingsField::TraceThreshold, OccluderMask::Alpha { threshold }) => step_fraction(threshold, 0.05),
+            (SettingsField::TraceThreshold, OccluderMask::Color { tolerance, .. }) => step_fraction(tolerance, 0.05),
+        }
+    }
+}
+
+// World units per texel of the distance field shadows the settings panel switches to
+const FIELD_TEXEL_SIZE: f32 = 4.0;
+
+// The masks the settings panel steps through: dark pixels, opaque pixels and pixels painted magenta
+const TRACE_MASK_STEPS: [OccluderMask; 3] = [
+    OccluderMask::Luminance { threshold: 0.02 },
+    OccluderMask::Alpha { threshold: 0.5 },
+    OccluderMask::Color { color: Color::FUCHSIA, tolerance: 0.1 },
+];
+
+#[derive(Component)]
+struct SettingsValue(SettingsField);
+
+// Explains what the shadow mode leaves out, or why it didn't change
+#[derive(Component, Default)]
+struct SettingsHint {
+    refused: bool,
+}
+
+#[derive(Component)]
+struct SettingsButton {
+    field: SettingsField,
+    direction: f32,
+}
+
+fn setup_settings_panel(mut commands: Commands, font_assets: Res<FontAssets>) {
+    let text_style = TextStyle { font: font_assets.fira_sans.clone(), font_size: 16.0, color: Color::WHITE };
+
+    commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect { left: Val::Px(0.0), top: Val::Px(0.0), ..default() },
+            size: Size::width(Val::Px(220.0)),
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(8.0)),
+            ..default()
+        },
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.8)),
+        ..default()
+    }).with_children(|panel| {
+        for field in SettingsField::FIELDS {
+            spawn_field_row(panel, field.label(), &text_style, (), SettingsValue(field), |direction| SettingsButton { field, direction });
+        }
+        panel.spawn((TextBundle {
+            text: Text::from_section("", TextStyle { font_size: 14.0, color: Color::GRAY, ..text_style.clone() }),
+            style: Style { max_size: Size::width(Val::Px(204.0)), ..default() },
+            ..default()
+        }, SettingsHint::default()));
+    });
+}
+
+fn update_settings_panel(
+    tracing: Res<OccluderTracing>,
+    illumination: Res<GlobalIllumination>,
+    quality: Res<LightingQuality>,
+    mut value_q: Query<(&mut Text, &SettingsValue)>,
+    mut hint_q: Query<(&mut Text, &SettingsHint), Without<SettingsValue>>,
+) {
+    for (mut text, value) in value_q.iter_mut() {
+        text.sections[0].value = value.0.value(&tracing, &illumination, &quality);
+    }
+    for (mut text, hint) in hint_q.iter_mut() {
+        text.sections[0].value = match (illumination.shadows, hint.refused) {
+            (ShadowMode::DistanceField { .. }, _) => {
+                "Field shadows make every wall opaque and ignore light layers and wall heights".to_string()
+            }
+            (ShadowMode::Segments, true) => {
+                "Field shadows can't show light layers, wall heights or translucent walls, remove them first".to_string()
+            }
+            (ShadowMode::Segments, false) => String::new(),
+        };
+    }
+}
+
+fn handle_settings_buttons(
+    interaction_q: Query<(&Interaction, &SettingsButton), Changed<Interaction>>,
+    mut tracing: ResMut<OccluderTracing>,
+    mut illumination: ResMut<GlobalIllumination>,
+    mut quality: ResMut<LightingQuality>,
+    occluder_q: Query<&LightOccluder>,
+    light_q: Query<&LightSource>,
+    mut hint_q: Query<&mut SettingsHint>,
+) {
+    for (interaction, button) in interaction_q.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        let refused = button.field == SettingsField::Shadows
+            && illumination.shadows == ShadowMode::Segments
+            && !DistanceField::supports(occluder_q.iter(), light_q.iter());
+        for mut hint in hint_q.iter_mut() {
+            hint.refused = refused;
+        }
+        if !refused {
+            button.field.step(&mut tracing, &mut illumination, &mut quality, button.direction);
+        }
+    }
+}
+
 fn mouse_scroll(
     mut mouse_wheel_events: EventReader<MouseWheel>,
     mut query_list: Query<(&mut ScrollingList, &mut Style, &Children, &Node)>,
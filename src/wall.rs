@@ -4,20 +4,27 @@ use bevy_mod_raycast::RaycastMesh;
 use bevy_prototype_lyon::prelude::*;
 use bevy_pancam::*;
 
-use crate::{GameState, actions::{Actions, Tool, update_mouse_click}, components::{self, Deleteable, RaycastSet}, lighting::LightOccluder};
+use crate::{GameState, actions::{Actions, Tool, update_mouse_click}, components::{self, Deleteable, RaycastSet}, history::{Edit, EditHistory, EditObject}, level::WallData, lighting::{LightOccluder, OccluderShape}};
 
 pub struct WallBuildingPlugin;
 
 impl Plugin for WallBuildingPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(handle_wall_building.after(update_mouse_click).in_set(OnUpdate(GameState::Playing)));
+        app.add_system(handle_wall_building.after(update_mouse_click).in_set(OnUpdate(GameState::Playing)))
+            .add_system(handle_polygon_building.after(update_mouse_click).in_set(OnUpdate(GameState::Playing)));
     }
 }
 
+/// A rect wall that is still being drawn
 #[derive(Component)]
-struct PreliminaryWall;
+pub(crate) struct PreliminaryWall;
+
+/// A wall traced from the map image, see `trace_system`
+#[derive(Component)]
+pub(crate) struct TracedWall;
+
 // Builds a wall, also disables pancam and enables a preliminary wall
-fn handle_wall_building(mut actions: ResMut<Actions>, mut commands: Commands, mut preliminary_q: Query<(&mut PreliminaryWall, Entity, &mut Path, &Transform, &mut LightOccluder)>, mut pancam_q: Query<&mut PanCam>) {
+fn handle_wall_building(mut actions: ResMut<Actions>, mut commands: Commands, mut history: ResMut<EditHistory>, mut preliminary_q: Query<(&mut PreliminaryWall, Entity, &mut Path, &Transform, &mut LightOccluder)>, mut pancam_q: Query<&mut PanCam>) {
     // Create Preliminary Wall 
     let cursor = actions.world_cursor_position.unwrap();
     let mut pancam = pancam_q.single_mut();
@@ -25,18 +32,20 @@ fn handle_wall_building(mut actions: ResMut<Actions>, mut commands: Commands, mu
     if preliminary_q.iter().len() == 1{
         let (mut preliminary_wall, entity, mut path, transform, mut occluder) = preliminary_q.single_mut();
         let transf = transform.translation.truncate();
-        *path = GeometryBuilder::build_as(&shapes::Rectangle{
-            extents: Vec2::new(cursor.x, -cursor.y) - Vec2::new(transf.x, -transf.y),
-            origin: shapes::RectangleOrigin::TopLeft,
-            ..default()
-        });
+        occluder.shape = OccluderShape::Rect {
+            width: cursor.x - transf.x,
+            height: transf.y - cursor.y,
+        };
 
-        occluder.width = cursor.x - transf.x;
-        occluder.height = transf.y - cursor.y;
+        *path = wall_path(&occluder);
 
         if(actions.left_click) {
             commands.entity(entity).remove::<PreliminaryWall>();
             commands.entity(entity).insert(Deleteable);
+            history.record(Edit::Create {
+                entity,
+                object: EditObject::Wall(WallData::from_transform(transform, &occluder)),
+            });
             actions.revert_to_previous_tool();
             pancam.enabled = true;
         }
@@ -44,28 +53,146 @@ fn handle_wall_building(mut actions: ResMut<Actions>, mut commands: Commands, mu
     } 
     else if actions.left_click {
         if let Some(Tool::BuildWall) = actions.current_tool() {
-            let entity = commands.spawn(
-            (ShapeBundle {
-                path: GeometryBuilder::build_as(&shapes::Rectangle{
-                    extents: Vec2::new(0.0, 0.0),
-                    origin: shapes::RectangleOrigin::TopLeft,
-                    ..default()
+            let entity = commands.spawn((
+                wall_bundle(&WallData {
+                    position: cursor,
+                    rotation: 0.0,
+                    occluder: LightOccluder::rect(0.0, 0.0),
+                    traced: false,
                 }),
-                transform: Transform::from_translation(Vec3::new(cursor.x, cursor.y, 1.0)),
-                ..default()
-            },
-            Fill::color(Color::BLACK),
-            Stroke::new(Color::BLACK, 1.0),
-            PreliminaryWall,
-            PickableBundle::default(), 
-            LightOccluder {
-                width: 0.0,
-                height: 0.0,
-            }
-        )).id();
+                PreliminaryWall,
+            )).id();
 
         info!("Created entity {:?}", entity);
         pancam.enabled = false;
         }
     }
-}
\ No newline at end of file
+}
+
+/// A polygon wall that is still being built vertex by vertex
+#[derive(Component)]
+pub(crate) struct PreliminaryPolygon;
+
+// How close to the first vertex a click has to be to close the polygon
+const CLOSE_POLYGON_DISTANCE: f32 = 8.0;
+
+// Builds a polygon vertex by vertex. Clicking the first vertex again closes it, Enter finishes it as an open
+// polyline and Escape throws it away
+fn handle_polygon_building(mut actions: ResMut<Actions>, keyboard_input: Res<Input<KeyCode>>, mut commands: Commands, mut history: ResMut<EditHistory>, mut preliminary_q: Query<(Entity, &mut Path, &mut Fill, &Transform, &mut LightOccluder), With<PreliminaryPolygon>>, mut pancam_q: Query<&mut PanCam>) {
+    let Some(cursor) = actions.world_cursor_position else {
+        return;
+    };
+    let mut pancam = pancam_q.single_mut();
+
+    let Ok((entity, mut path, mut fill, transform, mut occluder)) = preliminary_q.get_single_mut() else {
+        if actions.left_click && actions.current_tool() == Some(Tool::BuildPolygon) {
+            commands.spawn((
+                wall_bundle(&WallData {
+                    position: cursor,
+                    rotation: 0.0,
+                    occluder: LightOccluder {
+                        shape: OccluderShape::Polyline(vec![Vec2::ZERO]),
+                        ..default()
+                    },
+                    traced: false,
+                }),
+                PreliminaryPolygon,
+            ));
+            pancam.enabled = false;
+        }
+        return;
+    };
+
+    if keyboard_input.just_pressed(KeyCode::Escape) || actions.current_tool() != Some(Tool::BuildPolygon) {
+        commands.entity(entity).despawn_recursive();
+        pancam.enabled = true;
+        return;
+    }
+
+    let OccluderShape::Polyline(points) = &mut occluder.shape else {
+        return;
+    };
+    let local_cursor = cursor - transform.translation.truncate();
+
+    let closing = actions.left_click && points.len() >= 3 && local_cursor.distance(points[0]) <= CLOSE_POLYGON_DISTANCE;
+    let finishing = keyboard_input.just_pressed(KeyCode::Return) && points.len() >= 2;
+
+    if closing || finishing {
+        let points = std::mem::take(points);
+        occluder.shape = if closing { OccluderShape::Polygon(points) } else { OccluderShape::Polyline(points) };
+        *path = wall_path(&occluder);
+        *fill = wall_fill(&occluder);
+
+        commands.entity(entity).remove::<PreliminaryPolygon>();
+        commands.entity(entity).insert(Deleteable);
+        history.record(Edit::Create {
+            entity,
+            object: EditObject::Wall(WallData::from_transform(transform, &occluder)),
+        });
+        actions.revert_to_previous_tool();
+        pancam.enabled = true;
+        return;
+    }
+
+    if actions.left_click {
+        points.push(local_cursor);
+    }
+
+    // Preview the next edge up to the cursor
+    let mut preview = points.clone();
+    preview.push(local_cursor);
+    *path = GeometryBuilder::build_as(&shapes::Polygon {
+        points: preview,
+        closed: false,
+    });
+}
+
+// Spawns a finished wall
+pub fn spawn_wall(commands: &mut Commands, wall: &WallData) -> Entity {
+    let mut entity = commands.spawn((wall_bundle(wall), Deleteable));
+    if wall.traced {
+        entity.insert(TracedWall);
+    }
+    entity.id()
+}
+
+fn wall_bundle(wall: &WallData) -> impl Bundle {
+    (
+        ShapeBundle {
+            path: wall_path(&wall.occluder),
+            transform: wall.transform(),
+            ..default()
+        },
+        wall_fill(&wall.occluder),
+        Stroke::new(Color::BLACK, 1.0),
+        PickableBundle::default(),
+        wall.occluder.clone(),
+    )
+}
+
+pub fn wall_path(occluder: &LightOccluder) -> Path {
+    match &occluder.shape {
+        OccluderShape::Rect { width, height } => GeometryBuilder::build_as(&shapes::Rectangle {
+            extents: Vec2::new(*width, *height),
+            origin: shapes::RectangleOrigin::TopLeft,
+            ..default()
+        }),
+        OccluderShape::Polygon(points) => GeometryBuilder::build_as(&shapes::Polygon {
+            points: points.clone(),
+            closed: true,
+        }),
+        OccluderShape::Polyline(points) => GeometryBuilder::build_as(&shapes::Polygon {
+            points: points.clone(),
+            closed: false,
+        }),
+        OccluderShape::Circle { radius } => GeometryBuilder::build_as(&shapes::Circle {
+            radius: *radius,
+            center: Vec2::ZERO,
+        }),
+    }
+}
+
+// Polylines don't enclose anything, so only their outline is drawn
+pub fn wall_fill(occluder: &LightOccluder) -> Fill {
+    Fill::color(if occluder.is_closed() { Color::BLACK } else { Color::NONE })
+}
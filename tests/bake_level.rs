@@ -0,0 +1,94 @@
+//! Bakes a small level the way the `bake` subcommand does and checks the lightmap it writes
+
+use std::{fs, path::PathBuf};
+
+use bevy::prelude::*;
+use bevy_game::{
+    bake_level,
+    level::{LevelFile, LightData, WallData},
+    lighting::{LightOccluder, LightSource},
+};
+
+// A fresh directory per test, so tests running at the same time don't share files
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bevy_game_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn bakes_static_lights_relative_to_the_asset_directory() {
+    let dir = scratch_dir("bake_level");
+    let assets = dir.join("assets");
+    fs::create_dir_all(assets.join("textures")).unwrap();
+    image::save_buffer_with_format(
+        assets.join("textures/map.png"),
+        &[128; 32 * 32 * 4],
+        32,
+        32,
+        image::ColorType::Rgba8,
+        image::ImageFormat::Png,
+    )
+    .unwrap();
+
+    let light =
+        LightSource { color: Vec4::ONE, intensity: 1.0, radius: 12.0, is_active: 1, is_static: true, ..default() };
+    let level = LevelFile {
+        background: "textures/map.png".to_string(),
+        // A wall right of the light, from x = 4 to 6
+        walls: vec![WallData {
+            position: Vec2::new(4.0, 16.0),
+            rotation: 0.0,
+            occluder: LightOccluder::rect(2.0, 32.0),
+            traced: false,
+        }],
+        lights: vec![
+            LightData { position: Vec2::ZERO, rotation: 0.0, light, animation: None },
+            // Dynamic lights stay out of the lightmap
+            LightData {
+                position: Vec2::new(-10.0, -10.0),
+                rotation: 0.0,
+                light: LightSource { is_static: false, ..light },
+                animation: None,
+            },
+        ],
+        ..default()
+    };
+    let level_path = dir.join("level.ron");
+    level.write(&level_path).unwrap();
+
+    let output = dir.join("lightmap.png");
+    let written = bake_level(&level_path, &assets, Some(&output), None).unwrap();
+    assert_eq!(written, output);
+
+    let lightmap = image::open(&output).unwrap().into_rgba8();
+    assert_eq!(lightmap.dimensions(), (32, 32));
+    // The map is centered on the origin, so texel (x, y) covers the world around (x - 15.5, 15.5 - y)
+    let texel = |world: Vec2| lightmap.get_pixel((world.x + 16.0) as u32, (16.0 - world.y) as u32).0;
+
+    let next_to_light = texel(Vec2::new(1.0, 0.0));
+    assert!(next_to_light[0] > 100, "{next_to_light:?}");
+    assert_eq!(next_to_light[0], next_to_light[1]);
+    assert_eq!(next_to_light[1], next_to_light[2]);
+    // Further away is darker, behind the wall and outside of the radius is dark
+    assert!(texel(Vec2::new(-6.0, 0.0))[0] < next_to_light[0]);
+    assert!(texel(Vec2::new(-6.0, 0.0))[0] > 0);
+    assert_eq!(texel(Vec2::new(8.0, 0.0))[0], 0);
+    assert_eq!(texel(Vec2::new(0.0, 14.0))[0], 0);
+    // Only the dynamic light would light this
+    assert_eq!(texel(Vec2::new(-10.0, -10.0))[0], 0);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn missing_background_is_an_error() {
+    let dir = scratch_dir("bake_missing");
+    let level = LevelFile { background: "textures/missing.png".to_string(), ..default() };
+    let level_path = dir.join("level.ron");
+    level.write(&level_path).unwrap();
+
+    assert!(bake_level(&level_path, &dir, None, None).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,129 @@
+//! Renders a small fixed level with the CPU version of the lighting shader and compares it to a checked-in
+//! reference image. Run with `UPDATE_GOLDEN=1` to write a new reference after an intended change to the lighting.
+
+use std::path::Path;
+
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use bevy_game::lighting::{
+    render_lightmap, FalloffCurves, GlobalIllumination, LightOccluder, LightShape, LightSource, OccluderShape, Spot,
+    UnsupportedFormat,
+};
+
+type Lights = Vec<(LightSource, Transform)>;
+type Occluders = Vec<(LightOccluder, Transform)>;
+
+const REFERENCE: &str = "tests/golden/lit_level.png";
+
+/// Texels may be off by this much, float math isn't exactly the same everywhere
+const TOLERANCE: u8 = 2;
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+
+// A grey checkerboard, so darkened and tinted areas are easy to spot
+fn map() -> Image {
+    let data = (0..WIDTH * HEIGHT)
+        .flat_map(|index| {
+            let (x, y) = (index % WIDTH, index / WIDTH);
+            let value = if (x / 8 + y / 8) % 2 == 0 { 200 } else { 120 };
+            [value, value, value, u8::MAX]
+        })
+        .collect();
+    Image::new(
+        Extent3d { width: WIDTH, height: HEIGHT, ..default() },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+fn light(color: Color, intensity: f32, radius: f32) -> LightSource {
+    LightSource { color: Vec4::from(color.as_linear_rgba_f32()), intensity, radius, is_active: 1, ..default() }
+}
+
+// One light of every kind, with an opaque wall, a translucent red pane and a low wall the spotlight shines over
+fn level() -> (Lights, Occluders) {
+    let lights = vec![
+        (LightSource { source_radius: 2.0, ..light(Color::WHITE, 1.5, 40.0) }, Transform::from_xyz(-18.0, 8.0, 0.0)),
+        (
+            LightSource { spot: Some(Spot::default()), height: 20.0, ..light(Color::rgb(0.4, 0.6, 1.0), 2.0, 50.0) },
+            Transform::from_xyz(24.0, 16.0, 0.0).with_rotation(Quat::from_rotation_z(-2.5)),
+        ),
+        (
+            LightSource { shape: LightShape::Line { length: 12.0 }, ..light(Color::YELLOW, 1.0, 20.0) },
+            Transform::from_xyz(8.0, -18.0, 0.0),
+        ),
+    ];
+
+    let occluders = vec![
+        (LightOccluder::rect(4.0, 12.0), Transform::from_xyz(-6.0, 12.0, 0.0)),
+        (
+            LightOccluder { transmittance: Color::RED, opacity: 0.5, ..LightOccluder::rect(10.0, 3.0) },
+            Transform::from_xyz(-24.0, -4.0, 0.0),
+        ),
+        (
+            LightOccluder {
+                shape: OccluderShape::Polyline(vec![Vec2::new(0.0, 0.0), Vec2::new(12.0, -6.0)]),
+                height: Some(10.0),
+                ..default()
+            },
+            Transform::from_xyz(6.0, 4.0, 0.0),
+        ),
+    ];
+
+    (lights, occluders)
+}
+
+#[test]
+fn lighting_matches_reference() {
+    let (lights, occluders) = level();
+    let lit = render_lightmap(
+        &map(),
+        None,
+        None,
+        &lights,
+        &occluders,
+        &FalloffCurves::default(),
+        &GlobalIllumination::default(),
+    )
+    .unwrap();
+
+    let reference_path = Path::new(env!("CARGO_MANIFEST_DIR")).join(REFERENCE);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        image::save_buffer_with_format(
+            &reference_path,
+            &lit.data,
+            WIDTH,
+            HEIGHT,
+            image::ColorType::Rgba8,
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+        return;
+    }
+
+    let reference = image::open(&reference_path).unwrap().into_rgba8();
+    assert_eq!(reference.dimensions(), (WIDTH, HEIGHT));
+    for (index, (rendered, expected)) in lit.data.chunks_exact(4).zip(reference.pixels()).enumerate() {
+        let off = rendered.iter().zip(expected.0).any(|(rendered, expected)| rendered.abs_diff(expected) > TOLERANCE);
+        assert!(
+            !off,
+            "pixel ({}, {}) is {:?} instead of {:?}",
+            index as u32 % WIDTH,
+            index as u32 / WIDTH,
+            rendered,
+            expected.0
+        );
+    }
+}
+
+#[test]
+fn unsupported_formats_are_errors() {
+    let mut map = map();
+    map.texture_descriptor.format = TextureFormat::Rgba16Float;
+    let result = render_lightmap(&map, None, None, &[], &[], &FalloffCurves::default(), &GlobalIllumination::default());
+    assert_eq!(result.err(), Some(UnsupportedFormat(TextureFormat::Rgba16Float)));
+}